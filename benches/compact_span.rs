@@ -0,0 +1,58 @@
+//! Compares memory usage and conversion speed of `Span` and `CompactSpan`.
+//!
+//! Run with `cargo bench --bench compact_span`.
+
+extern crate xswag_base as base;
+
+use base::code::{BytePos, CompactSpan, ExpnId, Span};
+use std::mem::size_of;
+use std::time::Instant;
+
+const NUM_SPANS: u32 = 1_000_000;
+
+/// Generates spans resembling the ones of a typical AST: mostly short, some
+/// from macro expansions and a few very long ones (e.g. whole functions).
+fn spans() -> Vec<Span> {
+    (0..NUM_SPANS).map(|i| {
+        let lo = BytePos(i * 8);
+        let len = match i % 1000 {
+            0 => 100_000,
+            n => n % 40,
+        };
+        let ctxt = if i % 10 == 0 { ExpnId(i % 300) } else { ExpnId::root() };
+        Span::new(lo, lo + BytePos(len)).with_ctxt(ctxt)
+    }).collect()
+}
+
+fn main() {
+    let spans = spans();
+
+    let start = Instant::now();
+    let compact: Vec<CompactSpan> = spans.iter().map(|&s| s.into()).collect();
+    let encode_time = start.elapsed();
+
+    let start = Instant::now();
+    let decoded: Vec<Span> = compact.iter().map(|&s| s.into()).collect();
+    let decode_time = start.elapsed();
+    assert_eq!(decoded, spans);
+
+    let interned = compact.iter().filter(|s| s.is_interned()).count();
+    let full_size = spans.len() * size_of::<Span>();
+    let compact_size = compact.len() * size_of::<CompactSpan>()
+        + interned * size_of::<Span>();
+
+    println!("spans:           {}", spans.len());
+    println!("interned:        {} ({:.2}%)",
+        interned,
+        100.0 * interned as f64 / spans.len() as f64);
+    println!("`Span`:          {} bytes", full_size);
+    println!("`CompactSpan`:   {} bytes (+ interner overhead)", compact_size);
+    println!("savings:         {:.1}%",
+        100.0 * (1.0 - compact_size as f64 / full_size as f64));
+    println!("encoding:        {:?} ({:.1} ns/span)",
+        encode_time,
+        encode_time.as_nanos() as f64 / spans.len() as f64);
+    println!("decoding:        {:?} ({:.1} ns/span)",
+        decode_time,
+        decode_time.as_nanos() as f64 / spans.len() as f64);
+}
@@ -3,11 +3,10 @@ extern crate xswag_base as base;
 use base::code::{Span, FileMap};
 use base::diag;
 
-const TEXT: &'static str = "no-content";
+const TEXT: &str = "no-content";
 
 fn main() {
     let file = FileMap::new("src/examples/dummy.txt", TEXT);
-    file.find_lines();
 
     let e = diag::Report::simple_error(
         "Oh noes, this `Report` contains a dummy span :(",
@@ -0,0 +1,58 @@
+extern crate xswag_base as base;
+
+use base::code::{BytePos, CodeMap, FileMap, Origin, Span, SrcOffset};
+use base::diag::{self, Report};
+
+const TEMPLATE: &str = "\
+fn greet() {
+    print({{greeting}});
+}
+";
+
+fn main() {
+    let mut map = CodeMap::new();
+    let template = map.add_file("src/examples/greet.xs", TEMPLATE);
+
+    // "Preprocess" the template by substituting the placeholder
+    let placeholder = "{{greeting}}";
+    let greeting = "\"hello\" + 1";
+    let offset = TEMPLATE.find(placeholder).unwrap();
+    let generated_src = TEMPLATE.replace(placeholder, greeting);
+
+    let pos = |p: usize| BytePos(p as SrcOffset);
+    let original = |p: usize| template.start_pos() + pos(p);
+    let after = offset + placeholder.len();
+    let generated_after = offset + greeting.len();
+
+    let mut generated = FileMap::new("<preprocessed greet.xs>", generated_src);
+    generated.add_origin(Origin::copied(
+        Span::new(pos(0), pos(offset)),
+        original(0),
+    ));
+    generated.add_origin(Origin::generated(
+        Span::new(pos(offset), pos(generated_after)),
+        Span::new(original(offset), original(after)),
+    ));
+    generated.add_origin(Origin::copied(
+        Span::new(pos(generated_after), pos(generated.src().len())),
+        original(after),
+    ));
+    let generated = map.add_filemap(generated);
+
+    // The "lexer" only sees the generated code
+    let span_of = |needle: &str| {
+        let lo = generated.start_pos()
+            + pos(generated.src().find(needle).unwrap());
+        Span::new(lo, lo + pos(needle.len()))
+    };
+
+    let e = Report::simple_error(
+        "cannot add `{integer}` to `&str`",
+        span_of("\"hello\" + 1"),
+    ).with_span_note(
+        "in this function",
+        span_of("fn greet()"),
+    );
+
+    diag::print(&e, &map, diag::PrintOptions::default());
+}
@@ -0,0 +1,46 @@
+extern crate xswag_base as base;
+
+use base::code::{CodeMap, FileMap};
+use base::diag::{self, Report};
+
+const MAIN: &str = "\
+// entry point
+include \"util.xs\";
+
+fn main() {}
+";
+
+const UTIL: &str = "\
+include \"strings.xs\";
+";
+
+const STRINGS: &str = "\
+fn concat(a, b) {
+    a ++ b
+}
+";
+
+fn main() {
+    let mut map = CodeMap::new();
+
+    let span_of = |file: &FileMap, needle: &str| {
+        file.find_span(needle).unwrap()
+    };
+
+    let main = map.add_file("src/examples/main.xs", MAIN);
+    let util = map.add_filemap(
+        FileMap::new("src/examples/util.xs", UTIL)
+            .with_included_from(span_of(&main, "include \"util.xs\";")),
+    );
+    let strings = map.add_filemap(
+        FileMap::new("src/examples/strings.xs", STRINGS)
+            .with_included_from(span_of(&util, "include \"strings.xs\";")),
+    );
+
+    let e = Report::simple_error(
+        "unknown operator `++`",
+        span_of(&strings, "++"),
+    );
+
+    diag::print(&e, &map, diag::PrintOptions::default());
+}
@@ -0,0 +1,29 @@
+extern crate xswag_base as base;
+
+use base::code::{CodeMap, ExpnInfo};
+use base::diag::{self, Report};
+
+const MAIN: &str = "\
+macro square(x) { x * x }
+
+fn main() {
+    print(square!(\"hi\"));
+}
+";
+
+fn main() {
+    let mut map = CodeMap::new();
+    let file = map.add_file("src/examples/macro.xs", MAIN);
+
+    let span_of = |needle: &str| file.find_span(needle).unwrap();
+
+    // The multiplication in the macro body was expanded at the invocation
+    let expn = map.add_expansion(
+        ExpnInfo::new("square", span_of("square!(\"hi\")"))
+            .with_def_site(span_of("macro square(x) { x * x }"))
+    );
+    let mul = span_of("x * x").with_ctxt(expn);
+
+    let e = Report::simple_error("cannot multiply two strings", mul);
+    diag::print(&e, &map, diag::PrintOptions::default());
+}
@@ -0,0 +1,37 @@
+extern crate xswag_base as base;
+
+use base::code::{CodeMap, FileMap};
+use base::diag::{self, Report};
+
+const LIB: &str = "\
+fn helper() {}
+fn train() {}
+";
+
+const MAIN: &str = "\
+import lib;
+
+fn train() {
+    helper();
+}
+";
+
+fn main() {
+    let mut map = CodeMap::new();
+    let lib = map.add_file("src/examples/lib.xs", LIB);
+    let main = map.add_file("src/examples/main.xs", MAIN);
+
+    let span_of = |file: &FileMap, needle: &str| {
+        file.find_span(needle).unwrap()
+    };
+
+    let e = Report::simple_error(
+        "function `train` is defined multiple times",
+        span_of(&main, "fn train()"),
+    ).with_span_note(
+        "previously defined here",
+        span_of(&lib, "fn train()"),
+    );
+
+    diag::print(&e, &map, diag::PrintOptions::default());
+}
@@ -1,10 +1,10 @@
 extern crate xswag_base as base;
 extern crate env_logger;
 
-use base::code::{SrcOffset, BytePos, Span, FileMap};
+use base::code::FileMap;
 use base::diag::{Report, Remark, Snippet};
 
-const TEXT: &'static str = "\
+const TEXT: &str = "\
 You're waiting for a train.
 A train that'll take you far cheese
 	You know where you hope this triain will take you.
@@ -19,36 +19,32 @@ fn main() {
     env_logger::init().unwrap();
 
     let file = FileMap::new("src/examples/train.txt", TEXT);
-    file.find_lines();
 
-    let first = TEXT.find("train").unwrap() as SrcOffset;
-    let second = TEXT.find("triain").unwrap() as SrcOffset;
+    let first = file.find_span("train").unwrap();
+    let second = file.find_word("triain").unwrap();
 
-    let block_lo = TEXT.find("Yet it").unwrap() as SrcOffset;
-    let block_hi = TEXT.find("? Because").unwrap() as SrcOffset + 1;
+    let block = file.find_span("Yet it").unwrap()
+        .hull(&file.find_span("you?").unwrap());
 
-    let cheese = TEXT.find("cheese").unwrap() as SrcOffset;
-
-    let hal9 = TEXT.find("HAL9000").unwrap() as SrcOffset;
-    let king = TEXT.find("King").unwrap() as SrcOffset + 4;
+    let cheese_span = file.find_word("cheese").unwrap();
 
+    let quote_span = file.find_span("HAL9000").unwrap()
+        .hull(&file.find_span("King").unwrap());
 
     let e = Report::simple_error(
         "unknown symbol `trian`. Did you mean `train`?",
-        Span::new(BytePos(second), BytePos(second + 6)),
+        second,
     ).with_span_note(
         "symbol `train` was previously defined here",
-        Span::new(BytePos(first), BytePos(first + 5)),
+        first,
     );
 
-    let cheese_span = Span::new(BytePos(cheese), BytePos(cheese + 6));
     let e2 = Report::simple_error("incorrect quote from movie", cheese_span)
         .with_remark(Remark::note(
             "consider replacing it as shown below",
             Snippet::Replace { span: cheese_span, with: "away.".into() },
         ));
 
-    let quote_span = Span::new(BytePos(hal9), BytePos(king));
     let e3 = Report::simple_error("this is just wrong!", quote_span)
         .with_remark(Remark::note(
             "please use the real source instead",
@@ -62,7 +58,7 @@ fn main() {
         "that's a good question. I would be terrified the whole time not \
         knowing where that train would take me. I mean: it could be a bad \
         place! I really hope I triggered a line break by now...",
-        Span::new(BytePos(block_lo), BytePos(block_hi)),
+        block,
     ).with_note("maybe because you are together?");
 
     let opts = base::diag::PrintOptions::default();
@@ -0,0 +1,278 @@
+use super::{SrcOffset, BytePos, Span, Loc, FileMap, FileName, ExpnId, ExpnInfo};
+use std::sync::Arc;
+
+/// Owns all files of one compilation and assigns each of them a disjoint
+/// range of `BytePos`. This way any position or span can be resolved back to
+/// the file it belongs to.
+///
+/// Between two files there is always a gap of one byte. Thus the position
+/// directly after the last byte of a file (e.g. the position of an empty
+/// span at the very end) is never the first byte of the next file.
+#[derive(Debug, Default)]
+pub struct CodeMap {
+    /// All files in the order they were added (and thus ordered by their
+    /// start position)
+    files: Vec<Arc<FileMap>>,
+    /// Information about all macro expansions. The expansion with id `n` is
+    /// stored at index `n - 1`, since 0 is the root context.
+    expansions: Vec<ExpnInfo>,
+}
+
+impl CodeMap {
+    /// Creates an empty codemap.
+    pub fn new() -> CodeMap {
+        CodeMap {
+            files: Vec::new(),
+            expansions: Vec::new(),
+        }
+    }
+
+    /// Creates a new `FileMap` from the given filename and content and adds
+    /// it to this codemap.
+    pub fn add_file<U, V>(&mut self, filename: U, src: V) -> Arc<FileMap>
+        where U: Into<FileName>,
+              V: Into<String>
+    {
+        self.add_filemap(FileMap::new(filename, src))
+    }
+
+    /// Adds an existing `FileMap` to this codemap. The file is moved to the
+    /// next free position range, so all positions previously obtained from
+    /// it are invalid afterwards.
+    pub fn add_filemap(&mut self, mut file: FileMap) -> Arc<FileMap> {
+        let start = self.files.last()
+            .map(|f| f.end_pos() + BytePos(1))
+            .unwrap_or(BytePos(0));
+        file.set_start_pos(start);
+
+        let file = Arc::new(file);
+        self.files.push(file.clone());
+        file
+    }
+
+    /// Returns all files in the order they were added.
+    pub fn files(&self) -> &[Arc<FileMap>] {
+        &self.files
+    }
+
+    /// Returns the file containing the given position or `None` if the
+    /// position does not belong to any file.
+    pub fn lookup_file(&self, pos: BytePos) -> Option<&Arc<FileMap>> {
+        // Find the last file starting at or before `pos`
+        let idx = match self.files.binary_search_by(|f| f.start_pos().cmp(&pos)) {
+            Ok(idx) => idx,
+            Err(0) => return None,
+            Err(idx) => idx - 1,
+        };
+
+        let file = &self.files[idx];
+        if file.contains_pos(pos) {
+            Some(file)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the first file with the given name or `None` if there is no
+    /// such file.
+    pub fn file_by_name(&self, name: &FileName) -> Option<&Arc<FileMap>> {
+        self.files.iter().find(|f| f.filename() == name)
+    }
+
+    /// Returns the file containing the given position together with the
+    /// location (line and column) of the position within that file. Like
+    /// with `FileMap::get_loc`, the line honours line directives and thus
+    /// can't be used to index the file (see `FileMap::get_physical_loc`).
+    pub fn lookup_loc(&self, pos: BytePos) -> Option<(&Arc<FileMap>, Loc)> {
+        self.lookup_file(pos).map(|f| (f, f.get_loc(pos)))
+    }
+
+    /// Returns the file containing the whole span. Returns `None` if the span
+    /// is a dummy span or if its start and end lie in different files.
+    pub fn span_file(&self, span: Span) -> Option<&Arc<FileMap>> {
+        if span.is_dummy() {
+            return None;
+        }
+
+        self.lookup_file(span.lo).and_then(|f| {
+            if f.contains_pos(span.hi) {
+                Some(f)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Registers a new macro expansion and returns its id. Spans of the
+    /// expanded code should carry this id (see `Span::with_ctxt`).
+    pub fn add_expansion(&mut self, info: ExpnInfo) -> ExpnId {
+        self.expansions.push(info);
+        ExpnId(self.expansions.len() as u32)
+    }
+
+    /// Returns the information about the given expansion or `None` for the
+    /// root context and unknown ids.
+    pub fn expansion(&self, id: ExpnId) -> Option<&ExpnInfo> {
+        if id.is_root() {
+            None
+        } else {
+            self.expansions.get(id.0 as usize - 1)
+        }
+    }
+
+    /// Returns the total number of bytes of all files (not counting the gaps
+    /// between files).
+    pub fn total_len(&self) -> SrcOffset {
+        self.files.iter().map(|f| f.src().len() as SrcOffset).sum()
+    }
+}
+
+/// Types which can resolve positions to the `FileMap` they belong to. This
+/// is implemented by `CodeMap` and by `FileMap` itself (for the common case
+/// of only one file), so that functions like `diag::print` work with both.
+pub trait FileLookup {
+    /// Returns the file containing the given position or `None` if the
+    /// position is unknown.
+    fn lookup_filemap(&self, pos: BytePos) -> Option<&FileMap>;
+
+    /// Returns the file containing the whole span. Returns `None` if the span
+    /// is a dummy span or if it doesn't lie within one known file.
+    fn span_filemap(&self, span: Span) -> Option<&FileMap> {
+        if span.is_dummy() {
+            return None;
+        }
+
+        self.lookup_filemap(span.lo).and_then(|f| {
+            if f.contains_pos(span.hi) {
+                Some(f)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Returns information about the macro expansion with the given id. Only
+    /// types which manage expansions (like `CodeMap`) return anything.
+    fn lookup_expansion(&self, _: ExpnId) -> Option<&ExpnInfo> {
+        None
+    }
+
+    /// Returns the file if this lookup consists of exactly one file (like a
+    /// `FileMap`). Used as fallback for reports without any usable span.
+    fn single_filemap(&self) -> Option<&FileMap> {
+        None
+    }
+}
+
+impl FileLookup for CodeMap {
+    fn lookup_filemap(&self, pos: BytePos) -> Option<&FileMap> {
+        self.lookup_file(pos).map(|f| &**f)
+    }
+
+    fn lookup_expansion(&self, id: ExpnId) -> Option<&ExpnInfo> {
+        self.expansion(id)
+    }
+}
+
+impl FileLookup for FileMap {
+    fn lookup_filemap(&self, pos: BytePos) -> Option<&FileMap> {
+        if self.contains_pos(pos) {
+            Some(self)
+        } else {
+            None
+        }
+    }
+
+    fn single_filemap(&self) -> Option<&FileMap> {
+        Some(self)
+    }
+}
+
+// --- tests ---
+#[test]
+fn file_lookup() {
+    use super::{LineIdx, ColIdx};
+
+    let mut map = CodeMap::new();
+    let a = map.add_file("a.xs", "foo\nbar");
+    let b = map.add_file("b.xs", "");
+    let c = map.add_file("c.xs", "x\ny\nz");
+
+    assert_eq!(a.span(), Span::new(BytePos(0), BytePos(7)));
+    assert_eq!(b.span(), Span::new(BytePos(8), BytePos(8)));
+    assert_eq!(c.span(), Span::new(BytePos(9), BytePos(14)));
+    assert_eq!(map.total_len(), 12);
+
+    let name = |pos| {
+        map.lookup_file(BytePos(pos)).map(|f| f.filename().to_string())
+    };
+    assert_eq!(name(0).as_deref(), Some("a.xs"));
+    assert_eq!(name(7).as_deref(), Some("a.xs"));
+    assert_eq!(name(8).as_deref(), Some("b.xs"));
+    assert_eq!(name(9).as_deref(), Some("c.xs"));
+    assert_eq!(name(14).as_deref(), Some("c.xs"));
+    assert_eq!(name(15), None);
+
+    let (file, loc) = map.lookup_loc(BytePos(13)).unwrap();
+    assert_eq!(*file.filename(), FileName::from("c.xs"));
+    assert_eq!(loc, Loc { line: LineIdx(2), col: ColIdx(0) });
+    assert_eq!(file.get_line(loc.line), Some("z"));
+    assert_eq!(c.get_line_start(LineIdx(1)), Some(BytePos(11)));
+
+    let cross = Span::new(BytePos(5), BytePos(10));
+    assert!(map.span_file(cross).is_none());
+    assert!(map.span_file(Span::dummy()).is_none());
+    let inside = Span::new(BytePos(9), BytePos(12));
+    let c_name = FileName::from("c.xs");
+    assert_eq!(map.span_file(inside).map(|f| f.filename()), Some(&c_name));
+}
+
+#[test]
+fn lookup_trait() {
+    let mut map = CodeMap::new();
+    let a = map.add_file("a.xs", "foo");
+    let b = map.add_file("b.xs", "bar");
+
+    let s = Span::new(BytePos(4), BytePos(6));
+    let b_name = FileName::from("b.xs");
+    assert_eq!(map.span_filemap(s).map(|f| f.filename()), Some(&b_name));
+    assert!(a.span_filemap(s).is_none());
+    assert_eq!(b.span_filemap(s).map(|f| f.filename()), Some(&b_name));
+    assert!(b.lookup_filemap(BytePos(2)).is_none());
+    assert!(map.span_filemap(Span::dummy()).is_none());
+}
+
+#[test]
+fn expansions() {
+    let mut map = CodeMap::new();
+    map.add_file("a.xs", "foo!(bar!())");
+
+    let outer = map.add_expansion(
+        ExpnInfo::new("foo", Span::new(BytePos(0), BytePos(12)))
+    );
+    let inner = map.add_expansion(
+        ExpnInfo::new("bar", Span::new(BytePos(5), BytePos(11)).with_ctxt(outer))
+    );
+
+    assert!(map.expansion(ExpnId::root()).is_none());
+    assert!(map.expansion(ExpnId(3)).is_none());
+    assert_eq!(map.expansion(outer).unwrap().macro_name, "foo");
+    let info = map.lookup_expansion(inner).unwrap();
+    assert_eq!(info.macro_name, "bar");
+    assert_eq!(info.call_site.ctxt, outer);
+}
+
+#[test]
+fn includes() {
+    let mut map = CodeMap::new();
+    let main = map.add_file("main.xs", "include \"lib.xs\";");
+    let directive = Span::new(main.start_pos(), main.end_pos());
+    let lib = map.add_filemap(
+        FileMap::new("lib.xs", "fn helper() {}").with_included_from(directive)
+    );
+
+    assert_eq!(main.included_from(), None);
+    assert_eq!(lib.included_from(), Some(directive));
+    let parent = map.span_file(lib.included_from().unwrap()).unwrap();
+    assert_eq!(*parent.filename(), FileName::from("main.xs"));
+}
@@ -0,0 +1,174 @@
+use super::{BytePos, ExpnId, HasSpan, Span};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Mutex, OnceLock};
+
+// Layout of the inline encoding (tag bit cleared):
+//
+//   63  62 ........ 47  46 ........ 32  31 ........ 0
+//   tag      ctxt            len              lo
+//
+// A `len` with all bits set marks a dummy span. With the tag bit set, the
+// lower 32 bits are an index into the global span interner instead.
+const TAG_INTERNED: u64 = 1 << 63;
+const LEN_BITS: u32 = 15;
+const CTXT_BITS: u32 = 16;
+const LEN_MASK: u32 = (1 << LEN_BITS) - 1;
+const LEN_DUMMY: u32 = LEN_MASK;
+const MAX_LEN: u32 = LEN_MASK - 1;
+const MAX_CTXT: u32 = (1 << CTXT_BITS) - 1;
+
+/// An 8 byte representation of a `Span`, meant to be stored in large data
+/// structures like ASTs.
+///
+/// Most spans are short and don't stem from deeply nested expansions: those
+/// are encoded inline. All other spans are stored in a global interner and
+/// the compact span only holds an index. Converting back and forth via
+/// `From` is lossless in both cases.
+///
+/// Since the interner is never cleared, spans which need to be interned are
+/// kept alive until the program ends.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CompactSpan(u64);
+
+impl CompactSpan {
+    /// Encodes the given span, interning it if it can't be stored inline.
+    pub fn new(span: Span) -> CompactSpan {
+        let len = if span.is_dummy() {
+            Some(LEN_DUMMY)
+        } else if span.lo <= span.hi && span.len() <= MAX_LEN {
+            Some(span.len())
+        } else {
+            None
+        };
+
+        match len {
+            Some(len) if span.ctxt.0 <= MAX_CTXT => CompactSpan(
+                u64::from(span.lo.0)
+                    | (u64::from(len) << 32)
+                    | (u64::from(span.ctxt.0) << (32 + LEN_BITS))
+            ),
+            _ => CompactSpan(TAG_INTERNED | u64::from(intern(span))),
+        }
+    }
+
+    /// Decodes the full span.
+    pub fn get(self) -> Span {
+        if self.is_interned() {
+            lookup(self.0 as u32)
+        } else {
+            let lo = self.0 as u32;
+            let len = ((self.0 >> 32) as u32) & LEN_MASK;
+            let ctxt = ExpnId(((self.0 >> (32 + LEN_BITS)) as u32) & MAX_CTXT);
+            if len == LEN_DUMMY {
+                Span::dummy().with_ctxt(ctxt)
+            } else {
+                Span::new(BytePos(lo), BytePos(lo + len)).with_ctxt(ctxt)
+            }
+        }
+    }
+
+    /// Checks if the span is stored in the global interner instead of being
+    /// encoded inline.
+    pub fn is_interned(&self) -> bool {
+        self.0 & TAG_INTERNED != 0
+    }
+}
+
+impl Default for CompactSpan {
+    fn default() -> CompactSpan {
+        CompactSpan::new(Span::dummy())
+    }
+}
+
+impl From<Span> for CompactSpan {
+    fn from(span: Span) -> CompactSpan {
+        CompactSpan::new(span)
+    }
+}
+
+impl From<CompactSpan> for Span {
+    fn from(span: CompactSpan) -> Span {
+        span.get()
+    }
+}
+
+impl HasSpan for CompactSpan {
+    fn span(&self) -> Span {
+        self.get()
+    }
+}
+
+impl fmt::Debug for CompactSpan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.get().fmt(f)
+    }
+}
+
+#[derive(Default)]
+struct SpanInterner {
+    spans: Vec<Span>,
+    indices: HashMap<Span, u32>,
+}
+
+fn interner() -> &'static Mutex<SpanInterner> {
+    static INTERNER: OnceLock<Mutex<SpanInterner>> = OnceLock::new();
+    INTERNER.get_or_init(Default::default)
+}
+
+fn intern(span: Span) -> u32 {
+    // The interner is never left in an inconsistent state, so a poisoned
+    // lock can be used anyway
+    let mut interner = interner().lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(&idx) = interner.indices.get(&span) {
+        return idx;
+    }
+
+    let idx = interner.spans.len() as u32;
+    assert!(idx != u32::MAX, "too many interned spans");
+    interner.spans.push(span);
+    interner.indices.insert(span, idx);
+    idx
+}
+
+fn lookup(idx: u32) -> Span {
+    let interner = interner().lock().unwrap_or_else(|e| e.into_inner());
+    interner.spans[idx as usize]
+}
+
+// --- tests ---
+#[test]
+fn roundtrip() {
+    use std::mem::size_of;
+
+    assert_eq!(size_of::<CompactSpan>(), 8);
+
+    let s = |lo, hi| Span::new(BytePos(lo), BytePos(hi));
+    let inline = [
+        Span::dummy(),
+        Span::dummy().with_ctxt(ExpnId(3)),
+        s(3, 7),
+        s(u32::MAX - MAX_LEN, u32::MAX),
+        s(0, MAX_LEN),
+        s(10, 20).with_ctxt(ExpnId(MAX_CTXT)),
+    ];
+    for &span in &inline {
+        let compact = CompactSpan::from(span);
+        assert!(!compact.is_interned(), "{:?}", span);
+        assert_eq!(Span::from(compact), span);
+    }
+
+    let interned = [
+        s(0, MAX_LEN + 1),
+        s(1, LEN_DUMMY + 1),
+        s(7, 3),
+        s(10, 20).with_ctxt(ExpnId(MAX_CTXT + 1)),
+        s(0, u32::MAX).with_ctxt(ExpnId(u32::MAX)),
+    ];
+    for &span in &interned {
+        let compact = CompactSpan::from(span);
+        assert!(compact.is_interned(), "{:?}", span);
+        assert_eq!(Span::from(compact), span);
+        assert_eq!(CompactSpan::from(span), compact);
+    }
+}
@@ -0,0 +1,232 @@
+use super::{SrcOffset, BytePos, Span, LineIdx, ColIdx, Loc, FileMap};
+
+/// A cursor over the source of a `FileMap`, meant to be used by lexers. It
+/// yields the chars together with their positions and keeps track of the
+/// current line, so lexers never need to compute positions by hand.
+///
+/// To create a token, call `mark` at its start, consume its chars and then
+/// get its span via `span` (and its text via `token`).
+#[derive(Clone, Debug)]
+pub struct Cursor<'a> {
+    map: &'a FileMap,
+    /// Current position (relative to the start of the file)
+    pos: usize,
+    /// Position of the last call to `mark`
+    mark: usize,
+    /// Current (physical) line and the position of its start
+    line: LineIdx,
+    line_start: usize,
+    /// Start of the next line or `None` if this is the last line
+    next_line_start: Option<usize>,
+}
+
+impl<'a> Cursor<'a> {
+    /// Creates a cursor at the start of the given file.
+    pub fn new(map: &'a FileMap) -> Cursor<'a> {
+        let mut cursor = Cursor {
+            map,
+            pos: 0,
+            mark: 0,
+            line: LineIdx(0),
+            line_start: 0,
+            next_line_start: None,
+        };
+        cursor.next_line_start = cursor.line_start_of(LineIdx(1));
+        cursor
+    }
+
+    /// Returns the current position.
+    pub fn pos(&self) -> BytePos {
+        self.global(self.pos)
+    }
+
+    /// Returns the current (physical) line and byte column.
+    pub fn loc(&self) -> Loc {
+        Loc {
+            line: self.line,
+            col: ColIdx((self.pos - self.line_start) as SrcOffset),
+        }
+    }
+
+    /// Checks if all chars were consumed.
+    pub fn is_eof(&self) -> bool {
+        self.pos == self.src().len()
+    }
+
+    /// Returns the not yet consumed source.
+    pub fn rest(&self) -> &'a str {
+        &self.src()[self.pos..]
+    }
+
+    /// Returns the next char without consuming it.
+    pub fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    /// Returns the char `n` chars ahead without consuming anything.
+    /// `peek_nth(0)` is equivalent to `peek()`.
+    pub fn peek_nth(&self, n: usize) -> Option<char> {
+        self.rest().chars().nth(n)
+    }
+
+    /// Checks if the not yet consumed source starts with `s`.
+    pub fn starts_with(&self, s: &str) -> bool {
+        self.rest().starts_with(s)
+    }
+
+    /// Consumes and returns the next char.
+    pub fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.advance(c.len_utf8());
+        Some(c)
+    }
+
+    /// Consumes the next char if it equals `c`. Returns whether it did so.
+    pub fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.advance(c.len_utf8());
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Consumes `s` if the not yet consumed source starts with it. Returns
+    /// whether it did so.
+    pub fn eat_str(&mut self, s: &str) -> bool {
+        if self.starts_with(s) {
+            self.advance(s.len());
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Consumes chars as long as they satisfy the predicate and returns the
+    /// consumed text.
+    pub fn eat_while<F>(&mut self, mut pred: F) -> &'a str
+        where F: FnMut(char) -> bool
+    {
+        let start = self.pos;
+        let len = self.rest()
+            .find(|c| !pred(c))
+            .unwrap_or(self.rest().len());
+        self.advance(len);
+        &self.src()[start..self.pos]
+    }
+
+    /// Marks the current position as start of the next token.
+    pub fn mark(&mut self) {
+        self.mark = self.pos;
+    }
+
+    /// Returns the span from the last mark (or the start of the file) to the
+    /// current position.
+    pub fn span(&self) -> Span {
+        Span::new(self.global(self.mark), self.global(self.pos))
+    }
+
+    /// Returns the text consumed since the last mark (or the start of the
+    /// file).
+    pub fn token(&self) -> &'a str {
+        &self.src()[self.mark..self.pos]
+    }
+
+    fn src(&self) -> &'a str {
+        self.map.src()
+    }
+
+    fn global(&self, local: usize) -> BytePos {
+        self.map.start_pos() + BytePos(local as SrcOffset)
+    }
+
+    fn line_start_of(&self, line: LineIdx) -> Option<usize> {
+        self.map.get_line_start(line)
+            .map(|pos| (pos - self.map.start_pos()).0 as usize)
+    }
+
+    /// Moves the cursor `len` bytes forward, updating the current line. The
+    /// line starts are taken from the file's line table, so that the cursor
+    /// agrees with `FileMap::get_loc` about what a line break is.
+    fn advance(&mut self, len: usize) {
+        self.pos += len;
+        while let Some(next) = self.next_line_start {
+            if next > self.pos {
+                break;
+            }
+            self.line = self.line + LineIdx(1);
+            self.line_start = next;
+            self.next_line_start = self.line_start_of(self.line + LineIdx(1));
+        }
+    }
+}
+
+/// Yields the chars together with their positions.
+impl<'a> Iterator for Cursor<'a> {
+    type Item = (BytePos, char);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let pos = self.pos();
+        self.bump().map(|c| (pos, c))
+    }
+}
+
+// --- tests ---
+#[test]
+fn lexing() {
+    let mut map = FileMap::new("<dummy>", "let ä = 12;\r\n  x\n");
+    map.set_start_pos(BytePos(5));
+    let s = |lo, hi| Span::new(BytePos(lo), BytePos(hi));
+    let loc = |line, col| Loc { line: LineIdx(line), col: ColIdx(col) };
+
+    let mut tokens = Vec::new();
+    let mut cursor = map.cursor();
+    while !cursor.is_eof() {
+        cursor.eat_while(char::is_whitespace);
+        cursor.mark();
+        match cursor.peek() {
+            Some(c) if c.is_alphabetic() => {
+                cursor.eat_while(char::is_alphanumeric);
+            }
+            Some(c) if c.is_ascii_digit() => {
+                cursor.eat_while(|c| c.is_ascii_digit());
+            }
+            Some(_) => {
+                cursor.bump();
+            }
+            None => break,
+        }
+        tokens.push((cursor.token(), cursor.span()));
+        assert_eq!(map.get_physical_loc(cursor.pos()), cursor.loc());
+    }
+
+    assert_eq!(tokens, [
+        ("let", s(5, 8)),
+        ("ä", s(9, 11)),
+        ("=", s(12, 13)),
+        ("12", s(14, 16)),
+        (";", s(16, 17)),
+        ("x", s(21, 22)),
+    ]);
+    assert_eq!(cursor.loc(), loc(2, 0));
+}
+
+#[test]
+fn lookahead() {
+    let map = FileMap::new("<dummy>", "a\r\nb😀c");
+    let loc = |line, col| Loc { line: LineIdx(line), col: ColIdx(col) };
+
+    let mut cursor = Cursor::new(&map);
+    assert_eq!(cursor.peek(), Some('a'));
+    assert_eq!(cursor.peek_nth(4), Some('😀'));
+    assert!(cursor.eat('a'));
+    assert!(!cursor.eat('b'));
+    assert!(cursor.eat_str("\r\n"));
+    assert_eq!(cursor.loc(), loc(1, 0));
+
+    let rest: Vec<_> = cursor.by_ref().collect();
+    assert_eq!(rest, [(BytePos(3), 'b'), (BytePos(4), '😀'), (BytePos(8), 'c')]);
+    assert_eq!(cursor.loc(), loc(1, 6));
+    assert_eq!(cursor.bump(), None);
+    assert_eq!(cursor.span(), map.span());
+}
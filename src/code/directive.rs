@@ -0,0 +1,35 @@
+use super::{BytePos, FileName, LineIdx};
+
+/// A `#line`-style directive: from the line containing `pos` on, lines are
+/// reported as if they were lines of the file `filename` starting at line
+/// `line`. Directives are attached to a `FileMap` via
+/// `FileMap::add_line_directive`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub struct LineDirective {
+    /// Position from which on the directive applies
+    pub pos: BytePos,
+    /// Line index reported for the line containing `pos`
+    pub line: LineIdx,
+    /// File name to report or `None` to keep the current file name
+    pub filename: Option<FileName>,
+}
+
+impl LineDirective {
+    /// Creates a directive which only changes the reported line numbers.
+    pub fn new(pos: BytePos, line: LineIdx) -> LineDirective {
+        LineDirective {
+            pos,
+            line,
+            filename: None,
+        }
+    }
+
+    /// Sets the file name to report.
+    pub fn with_filename<F: Into<FileName>>(mut self, filename: F)
+        -> LineDirective
+    {
+        self.filename = Some(filename.into());
+        self
+    }
+}
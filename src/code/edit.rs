@@ -0,0 +1,107 @@
+use super::{SrcOffset, BytePos, Span};
+
+/// Describes a text edit which was applied to a `FileMap` (see
+/// `FileMap::edit`). It can be used to map positions and spans which were
+/// obtained before the edit to the corresponding positions after the edit.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub struct Edit {
+    /// The span which was replaced (positions before the edit)
+    pub span: Span,
+    /// Number of bytes of the inserted text
+    pub new_len: SrcOffset,
+}
+
+impl Edit {
+    /// Returns the span of the inserted text (positions after the edit).
+    pub fn new_span(&self) -> Span {
+        Span::new(self.span.lo, self.span.lo + BytePos(self.new_len))
+    }
+
+    /// Returns the number of bytes the file grew by. Negative if the file
+    /// shrunk.
+    pub fn delta(&self) -> i64 {
+        self.new_len as i64 - self.span.len() as i64
+    }
+
+    /// Maps a position from before the edit to the corresponding position
+    /// after the edit. Returns `None` if the position was inside of the
+    /// replaced span and thus doesn't exist anymore. The start and end of
+    /// the replaced span are mapped to the start and end of the inserted
+    /// text.
+    pub fn map_pos(&self, pos: BytePos) -> Option<BytePos> {
+        if pos <= self.span.lo {
+            Some(pos)
+        } else if pos >= self.span.hi {
+            Some(self.shift(pos))
+        } else {
+            None
+        }
+    }
+
+    /// Maps a span from before the edit to the corresponding span after the
+    /// edit:
+    ///
+    /// - spans before or after the replaced span are kept or shifted
+    /// - spans enclosing the replaced span grow or shrink accordingly
+    /// - spans partially overlapping the replaced span are shrunk to the
+    ///   part which was not replaced
+    /// - spans inside of the replaced span are invalidated (`None`)
+    ///
+    /// Dummy spans are returned unchanged. The expansion context of the span
+    /// is kept.
+    pub fn map_span(&self, span: Span) -> Option<Span> {
+        let (lo, hi) = (self.span.lo, self.span.hi);
+
+        let mapped = if span.is_dummy() || span.hi <= lo {
+            Some(span)
+        } else if span.lo >= hi {
+            Some(Span::new(self.shift(span.lo), self.shift(span.hi)))
+        } else if span.lo <= lo && span.hi >= hi {
+            Some(Span::new(span.lo, self.shift(span.hi)))
+        } else if span.lo >= lo && span.hi <= hi {
+            None
+        } else if span.lo < lo {
+            Some(Span::new(span.lo, lo))
+        } else {
+            Some(Span::new(self.new_span().hi, self.shift(span.hi)))
+        };
+        mapped.map(|s| s.with_ctxt(span.ctxt))
+    }
+
+    /// Shifts a position behind the replaced span by `delta`.
+    fn shift(&self, pos: BytePos) -> BytePos {
+        BytePos((pos.0 as i64 + self.delta()) as SrcOffset)
+    }
+}
+
+// --- tests ---
+#[test]
+fn span_mapping() {
+    let s = |lo, hi| Span::new(BytePos(lo), BytePos(hi));
+
+    // "0123456789" -> replace "345" with "ab"
+    let edit = Edit { span: s(3, 6), new_len: 2 };
+    assert_eq!(edit.delta(), -1);
+    assert_eq!(edit.new_span(), s(3, 5));
+
+    assert_eq!(edit.map_span(s(0, 3)), Some(s(0, 3)));
+    assert_eq!(edit.map_span(s(6, 9)), Some(s(5, 8)));
+    assert_eq!(edit.map_span(s(2, 7)), Some(s(2, 6)));
+    assert_eq!(edit.map_span(s(3, 6)), Some(s(3, 5)));
+    assert_eq!(edit.map_span(s(4, 5)), None);
+    assert_eq!(edit.map_span(s(1, 4)), Some(s(1, 3)));
+    assert_eq!(edit.map_span(s(5, 8)), Some(s(5, 7)));
+    assert_eq!(edit.map_span(Span::dummy()), Some(Span::dummy()));
+
+    assert_eq!(edit.map_pos(BytePos(3)), Some(BytePos(3)));
+    assert_eq!(edit.map_pos(BytePos(4)), None);
+    assert_eq!(edit.map_pos(BytePos(6)), Some(BytePos(5)));
+
+    // pure insertion of "xy" at 3
+    let edit = Edit { span: s(3, 3), new_len: 2 };
+    assert_eq!(edit.map_span(s(0, 3)), Some(s(0, 3)));
+    assert_eq!(edit.map_span(s(3, 5)), Some(s(5, 7)));
+    assert_eq!(edit.map_span(s(2, 4)), Some(s(2, 6)));
+    assert_eq!(edit.map_span(s(3, 3)), Some(s(3, 3)));
+}
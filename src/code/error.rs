@@ -0,0 +1,117 @@
+use super::{BytePos, Span, LineIdx, Loc, FileName};
+use std::error::Error;
+use std::fmt;
+
+/// Errors that can occur when converting between the different position
+/// types.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PosError {
+    /// The position doesn't lie within the file
+    OutOfBounds(BytePos),
+    /// The position points into the middle of a multibyte char
+    NotCharBoundary(BytePos),
+    /// There are less than `n` chars between the position and the end of
+    /// the file
+    NotEnoughChars {
+        pos: BytePos,
+        n: usize,
+    },
+    /// The end of the span lies before its start
+    ReversedSpan(Span),
+    /// The span is a dummy span and doesn't point to any code
+    DummySpan,
+    /// The line doesn't exist in the file
+    LineOutOfBounds(LineIdx),
+    /// The column lies past the end of the line (the position directly
+    /// before the line break is still valid)
+    ColPastEndOfLine(Loc),
+    /// The column points into the middle of a multibyte char
+    ColNotOnCharBoundary(Loc),
+    /// The end of a range lies before its start
+    ReversedRange {
+        lo: Loc,
+        hi: Loc,
+    },
+}
+
+impl fmt::Display for PosError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PosError::OutOfBounds(pos) => {
+                write!(f, "position {} is out of bounds", pos.0)
+            }
+            PosError::NotCharBoundary(pos) => {
+                write!(f, "position {} is not on a char boundary", pos.0)
+            }
+            PosError::NotEnoughChars { pos, n } => {
+                write!(f, "there are less than {} chars after position {}",
+                    n, pos.0)
+            }
+            PosError::ReversedSpan(span) => {
+                write!(f, "span {:?} ends before it starts", span)
+            }
+            PosError::DummySpan => write!(f, "dummy span"),
+            PosError::LineOutOfBounds(line) => {
+                write!(f, "line {} does not exist", line)
+            }
+            PosError::ColPastEndOfLine(loc) => {
+                write!(f, "column {} is past the end of line {}",
+                    loc.col, loc.line)
+            }
+            PosError::ColNotOnCharBoundary(loc) => {
+                write!(f, "column {} in line {} is not on a char boundary",
+                    loc.col, loc.line)
+            }
+            PosError::ReversedRange { lo, hi } => {
+                write!(f, "range end ({}) lies before its start ({})", hi, lo)
+            }
+        }
+    }
+}
+
+impl Error for PosError {}
+
+/// Errors that can occur when parsing a location string like `main.xs:12:5`
+/// (see `LocFormat`).
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum LocParseError {
+    /// The string doesn't have the form `file:line[:col][-line:col]`
+    InvalidSyntax(String),
+    /// A line or column is 0, although the format is 1-based
+    ZeroIndex,
+    /// A range was given where a single location was expected
+    UnexpectedRange,
+    /// There is no file with the given name
+    UnknownFile(FileName),
+    /// The location doesn't exist in the file
+    Pos(PosError),
+}
+
+impl From<PosError> for LocParseError {
+    fn from(e: PosError) -> LocParseError {
+        LocParseError::Pos(e)
+    }
+}
+
+impl fmt::Display for LocParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LocParseError::InvalidSyntax(ref s) => {
+                write!(f, "`{}` is not of the form `file:line[:col][-line:col]`",
+                    s)
+            }
+            LocParseError::ZeroIndex => {
+                write!(f, "lines and columns start at 1")
+            }
+            LocParseError::UnexpectedRange => {
+                write!(f, "expected a single location, found a range")
+            }
+            LocParseError::UnknownFile(ref name) => {
+                write!(f, "unknown file `{}`", name)
+            }
+            LocParseError::Pos(ref e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for LocParseError {}
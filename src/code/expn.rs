@@ -0,0 +1,52 @@
+use super::Span;
+
+/// Identifies the macro expansion a span was created by. Spans of code which
+/// was written directly in a file have the context `ExpnId::root()`. All
+/// other ids are handed out by `CodeMap::add_expansion` and can be resolved
+/// to an `ExpnInfo` via the codemap.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub struct ExpnId(pub u32);
+
+impl ExpnId {
+    /// The context of code which doesn't stem from a macro expansion.
+    pub fn root() -> ExpnId {
+        ExpnId(0)
+    }
+
+    /// Checks if this is the root context.
+    pub fn is_root(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+/// Information about one macro expansion.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub struct ExpnInfo {
+    /// Span of the macro invocation. If the invocation is itself part of an
+    /// expansion, the span's context points to that expansion.
+    pub call_site: Span,
+    /// Span of the macro definition, if it's known
+    pub def_site: Option<Span>,
+    /// Name of the expanded macro
+    pub macro_name: String,
+}
+
+impl ExpnInfo {
+    /// Creates the information of an expansion of the macro `macro_name`,
+    /// which was invoked at `call_site`.
+    pub fn new<S: Into<String>>(macro_name: S, call_site: Span) -> ExpnInfo {
+        ExpnInfo {
+            call_site,
+            def_site: None,
+            macro_name: macro_name.into(),
+        }
+    }
+
+    /// Sets the span of the macro definition.
+    pub fn with_def_site(mut self, def_site: Span) -> ExpnInfo {
+        self.def_site = Some(def_site);
+        self
+    }
+}
@@ -1,39 +1,85 @@
-use super::{SrcOffset, BytePos, LineIdx, ColIdx, Loc};
-use std::cell::RefCell;
+use super::{SrcOffset, BytePos, Span, LineIdx, ColIdx, Loc, ColUnit};
+use super::{PosError, Edit, Encoding, FileName, width};
+use super::{Lines, SpanLines, Origin, LineDirective, Cursor};
+use std::sync::OnceLock;
+use std::cmp::{min, max};
+use std::iter;
 use std::fmt;
 
 /// Stores the content of a file and keeps track of some position meta data,
 /// such as linebreaks.
 pub struct FileMap {
-    /// Original filename or dummy filename of the form "<...>"
-    filename: String,
+    /// Name of the original file
+    filename: FileName,
     /// The whole content of the file
     src: String,
-    /// List of line beginnings. Wrapped in a `RefCell`, because `FileMap`s
-    /// are shared a lot via `Rc` and this is the only thing requiring
-    /// mutability. Borrowing it will never panic, because:
-    /// - it's only borrowed within methods of `FileMap`
-    /// - the borrow always ends with those methods
-    /// - no method is called/active while another one is active (as long as
-    ///   no borrowing method is calling another borrowing method)
-    lines: RefCell<Vec<BytePos>>,
+    /// Encoding of the original file (the source is always stored as UTF-8)
+    encoding: Encoding,
+    /// Whether the original file started with a byte order mark
+    bom: bool,
+    /// Invalid parts of the original file which were replaced by U+FFFD
+    /// while decoding: position of the replacement char (relative to
+    /// `start_pos`) and length of the replaced bytes, sorted by position
+    replacements: Vec<(BytePos, SrcOffset)>,
+    /// Whether a lone '\r' is treated as line break
+    cr_line_breaks: bool,
+    /// Position of the first byte of this file within the whole codemap
+    start_pos: BytePos,
+    /// Whether the whole file consists of ASCII chars only. In that case all
+    /// column units are equal and conversions can be skipped.
+    is_ascii: bool,
+    /// List of line beginnings (relative to `start_pos`). It is computed
+    /// lazily on the first lookup and only once, even if the `FileMap` is
+    /// shared between threads via `Arc`. Use `line_table()` to access it.
+    lines: OnceLock<Vec<BytePos>>,
+    /// Origins of generated code, sorted and relative to `start_pos` (just
+    /// like the line table)
+    origins: Vec<Origin>,
+    /// Line directives, sorted and relative to `start_pos`
+    line_directives: Vec<LineDirective>,
+    /// Span of the include directive which loaded this file
+    included_from: Option<Span>,
+}
+
+/// Kinds of line endings used in a file.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub enum LineEnding {
+    /// `"\n"` (Unix)
+    Lf,
+    /// `"\r\n"` (Windows)
+    CrLf,
+    /// `"\r"` (classic Mac OS)
+    Cr,
+    /// Different kinds of line endings are used in one file
+    Mixed,
 }
 
 impl FileMap {
     /// Creates a new Filemap from existing buffers for the filename and
     /// content of the file.
     pub fn new<U, V>(filename: U, src: V) -> FileMap
-        where U: Into<String>,
+        where U: Into<FileName>,
               V: Into<String>
     {
+        let src = src.into();
         FileMap {
             filename: filename.into(),
-            src: src.into(),
-            lines: RefCell::new(vec![BytePos(0)]),
+            is_ascii: src.is_ascii(),
+            src,
+            encoding: Encoding::Utf8,
+            bom: false,
+            replacements: Vec::new(),
+            cr_line_breaks: false,
+            start_pos: BytePos(0),
+            lines: OnceLock::new(),
+            origins: Vec::new(),
+            line_directives: Vec::new(),
+            included_from: None,
         }
     }
 
-    pub fn filename(&self) -> &str {
+    pub fn filename(&self) -> &FileName {
         &self.filename
     }
 
@@ -41,66 +87,941 @@ impl FileMap {
         &self.src
     }
 
-    /// Adds a new line beginning with the given BytePos to the list. Line
-    /// beginnings need to be added in order!
+    /// Returns the encoding of the original file. This is `Utf8` unless the
+    /// file was loaded via `from_bytes` or `load_with`.
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Returns whether the original file started with a byte order mark,
+    /// which was stripped.
+    pub fn has_bom(&self) -> bool {
+        self.bom
+    }
+
+    /// Records the original encoding. Only used by the loading functions.
+    pub(super) fn set_encoding(&mut self, encoding: Encoding, bom: bool) {
+        self.encoding = encoding;
+        self.bom = bom;
+    }
+
+    /// Records the parts of the original file which were replaced while
+    /// decoding. Only used by the loading functions.
+    pub(super) fn set_replacements(&mut self,
+        replacements: Vec<(BytePos, SrcOffset)>)
+    {
+        self.replacements = replacements;
+    }
+
+    /// Returns the byte offset within the original file (as it was stored on
+    /// disk) corresponding to the given position or an error if the position
+    /// doesn't lie within this file. This takes a stripped byte order mark,
+    /// transcoding and invalid bytes replaced while decoding into account.
+    ///
+    /// After an `edit`, the offset refers to the edited source as it would be
+    /// stored in the original encoding. Replaced invalid bytes which were
+    /// overwritten by an edit are not taken into account anymore.
+    pub fn original_offset(&self, pos: BytePos) -> Result<usize, PosError> {
+        if !self.contains_pos(pos) {
+            return Err(PosError::OutOfBounds(pos));
+        }
+
+        let local = (pos - self.start_pos).0 as usize;
+        let bom_len = match self.encoding.bom() {
+            Some(bom) if self.bom => bom.len(),
+            _ => 0,
+        };
+        if self.encoding == Encoding::Utf8 && self.replacements.is_empty() {
+            return Ok(bom_len + local);
+        }
+
+        let mut replacements = self.replacements.iter().peekable();
+        let before: usize = self.src.char_indices()
+            .take_while(|&(i, _)| i < local)
+            .map(|(i, c)| match replacements.peek() {
+                Some(&&(pos, len)) if pos.0 as usize == i => {
+                    replacements.next();
+                    len as usize
+                }
+                _ => match self.encoding {
+                    Encoding::Utf8 => c.len_utf8(),
+                    Encoding::Latin1 => 1,
+                    Encoding::Utf16Le | Encoding::Utf16Be => 2 * c.len_utf16(),
+                },
+            })
+            .sum();
+        Ok(bom_len + before)
+    }
+
+    /// Returns the position of the first byte of this file. This is
+    /// `BytePos(0)` unless the file was added to a `CodeMap`.
+    pub fn start_pos(&self) -> BytePos {
+        self.start_pos
+    }
+
+    /// Returns the position directly after the last byte of this file.
+    pub fn end_pos(&self) -> BytePos {
+        self.start_pos + BytePos(self.src.len() as SrcOffset)
+    }
+
+    /// Returns the span covering the whole file.
+    pub fn span(&self) -> Span {
+        Span::new(self.start_pos, self.end_pos())
+    }
+
+    /// Checks if the given position lies within this file. The end position
+    /// (directly after the last byte) is considered part of the file.
+    pub fn contains_pos(&self, pos: BytePos) -> bool {
+        self.start_pos <= pos && pos <= self.end_pos()
+    }
+
+    /// Moves this file to a new start position. Only used by `CodeMap`.
+    pub(super) fn set_start_pos(&mut self, pos: BytePos) {
+        self.start_pos = pos;
+    }
+
+    /// Records that the code in `origin.generated` was generated from the
+    /// code in `origin.original`. Diagnostics pointing into the generated
+    /// code are then shown at the original code, if the original file is
+    /// part of the same `CodeMap`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the generated span is not within this file or overlaps with
+    /// the generated span of another origin.
+    pub fn add_origin(&mut self, origin: Origin) {
+        let generated = origin.generated;
+        assert!(self.check_span(generated).is_ok(),
+            "generated span {:?} is not within the file", generated);
+
+        let local = Origin {
+            generated: Span::new(
+                generated.lo - self.start_pos,
+                generated.hi - self.start_pos,
+            ),
+            ..origin
+        };
+        let idx = self.origins
+            .partition_point(|o| o.generated.lo < local.generated.lo);
+        let neighbors = &self.origins[idx.saturating_sub(1)..];
+        let overlaps = neighbors.iter().take(2).any(|o| {
+            o.generated.overlaps(local.generated)
+                || o.generated.lo == local.generated.lo
+        });
+        assert!(!overlaps,
+            "generated span {:?} overlaps with another origin", generated);
+
+        self.origins.insert(idx, local);
+    }
+
+    /// Returns an iterator over all origins of generated code in this file.
+    pub fn origins(&self) -> impl Iterator<Item = Origin> + '_ {
+        self.origins.iter().map(move |&o| self.to_global(o))
+    }
+
+    /// Returns the origin of the generated code containing the given span or
+    /// `None` if the span doesn't lie within one generated range.
+    pub fn origin_of(&self, span: Span) -> Option<Origin> {
+        if span.is_dummy() || !self.contains_pos(span.lo) {
+            return None;
+        }
+
+        let local = span.lo - self.start_pos;
+        let idx = self.origins.partition_point(|o| o.generated.lo <= local);
+        let origin = self.to_global(*self.origins.get(idx.checked_sub(1)?)?);
+        if origin.generated.contains(span) {
+            Some(origin)
+        } else {
+            None
+        }
+    }
+
+    /// Maps a span within generated code to the original code it was
+    /// generated from (see `Origin::map_span`). Returns `None` if the span
+    /// doesn't lie within one generated range.
+    pub fn original_span(&self, span: Span) -> Option<Span> {
+        self.origin_of(span).and_then(|o| o.map_span(span))
+    }
+
+    fn to_global(&self, origin: Origin) -> Origin {
+        Origin {
+            generated: Span::new(
+                self.start_pos + origin.generated.lo,
+                self.start_pos + origin.generated.hi,
+            ),
+            ..origin
+        }
+    }
+
+    /// Adds a line directive: from the line containing `directive.pos` on,
+    /// `get_loc` and diagnostics report lines as if they started at
+    /// `directive.line` in the file `directive.filename`. Directives apply
+    /// until the next directive. A later directive on the same line
+    /// overrides an earlier one.
+    ///
+    /// All other line indices (e.g. of `get_line`, `get_pos` and
+    /// `span_lines`) are physical lines and ignore the directives.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the position is not within this file.
+    pub fn add_line_directive(&mut self, directive: LineDirective) {
+        assert!(self.contains_pos(directive.pos),
+            "line directive at {:?} is not within the file", directive.pos);
+
+        let local = LineDirective {
+            pos: directive.pos - self.start_pos,
+            ..directive
+        };
+        let idx = self.line_directives
+            .partition_point(|d| d.pos <= local.pos);
+        self.line_directives.insert(idx, local);
+    }
+
+    /// Returns an iterator over all line directives of this file.
+    pub fn line_directives(&self)
+        -> impl Iterator<Item = LineDirective> + '_
+    {
+        self.line_directives.iter().map(move |d| LineDirective {
+            pos: self.start_pos + d.pos,
+            ..d.clone()
+        })
+    }
+
+    /// Returns the line index to report for the given (physical) line,
+    /// taking line directives into account.
+    pub fn reported_line(&self, line: LineIdx) -> LineIdx {
+        match self.line_directive_for(line) {
+            Some(d) => {
+                let directive_line = self.get_line_idx(self.start_pos + d.pos);
+                d.line + (line - directive_line)
+            }
+            None => line,
+        }
+    }
+
+    /// Returns the file name to report for the given (physical) line, taking
+    /// line directives into account.
+    pub fn reported_filename(&self, line: LineIdx) -> &FileName {
+        self.line_directives[..self.line_directives_until(line)]
+            .iter()
+            .rev()
+            .filter_map(|d| d.filename.as_ref())
+            .next()
+            .unwrap_or(&self.filename)
+    }
+
+    /// Returns the directive in effect at the given line.
+    fn line_directive_for(&self, line: LineIdx) -> Option<&LineDirective> {
+        self.line_directives_until(line)
+            .checked_sub(1)
+            .map(|idx| &self.line_directives[idx])
+    }
+
+    /// Returns the number of directives which are in effect at or before the
+    /// given line.
+    fn line_directives_until(&self, line: LineIdx) -> usize {
+        if self.line_directives.is_empty() {
+            return 0;
+        }
+
+        self.line_directives.partition_point(|d| {
+            self.get_line_idx(self.start_pos + d.pos) <= line
+        })
+    }
+
+    /// Formerly added a line beginning to the line table. Since the line
+    /// table is now computed lazily, this does nothing except checking (in
+    /// debug mode) that `offset` is indeed a line beginning. Lexers can use
+    /// a `Cursor` to keep track of lines instead.
+    #[deprecated(note = "the line table is computed lazily on first lookup")]
     pub fn add_line(&self, offset: BytePos) {
-        self.lines.borrow_mut().push(offset);
+        debug_assert!(
+            self.line_table().binary_search(&(offset - self.start_pos)).is_ok(),
+            "{:?} is not a line beginning", offset
+        );
     }
 
     /// Returns the number of lines.
     pub fn num_lines(&self) -> usize {
-        self.lines.borrow().len()
+        self.line_table().len()
     }
 
     /// Returns the (0-based) index of the line in which the byte with the
-    /// given `offset` lives.
+    /// given `offset` lives. The offset has to lie within this file.
     pub fn get_line_idx(&self, offset: BytePos) -> LineIdx {
         // If `offset` is not a line beginning, `binary_search` returns the
         // index of the next line. Hence `-1`.
-        LineIdx(self.lines.borrow()
-            .binary_search(&offset)
+        LineIdx(self.line_table()
+            .binary_search(&(offset - self.start_pos))
             .unwrap_or_else(|e| e - 1) as u32)
     }
 
     /// Returns the location of the given bytes as line and col numbers within
-    /// this file.
+    /// this file. The line honours line directives (see
+    /// `add_line_directive`).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the offset doesn't lie within this file. See `try_get_loc`
+    /// for a non-panicking version.
     pub fn get_loc(&self, offset: BytePos) -> Loc {
+        self.try_get_loc(offset).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Returns the location of the given bytes as line and col numbers within
+    /// this file or an error if the offset doesn't lie within this file. The
+    /// line honours line directives (see `add_line_directive`).
+    pub fn try_get_loc(&self, offset: BytePos) -> Result<Loc, PosError> {
+        self.try_get_physical_loc(offset).map(|loc| Loc {
+            line: self.reported_line(loc.line),
+            col: loc.col,
+        })
+    }
+
+    /// Like `get_loc`, but line directives are ignored. The line can be used
+    /// to index this file, e.g. with `get_line`.
+    pub fn get_physical_loc(&self, offset: BytePos) -> Loc {
+        self.try_get_physical_loc(offset).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Like `try_get_loc`, but line directives are ignored.
+    pub fn try_get_physical_loc(&self, offset: BytePos)
+        -> Result<Loc, PosError>
+    {
+        if !self.contains_pos(offset) {
+            return Err(PosError::OutOfBounds(offset));
+        }
+
         let line = self.get_line_idx(offset);
-        let col = offset - self.lines.borrow()[line.0 as usize];
+        let line_start = self.line_table()[line.0 as usize];
+        let col = offset - self.start_pos - line_start;
+
+        Ok(Loc { line, col: ColIdx(col.0) })
+    }
+
+    /// Checks that the position lies within this file and on a char
+    /// boundary.
+    pub fn check_pos(&self, pos: BytePos) -> Result<(), PosError> {
+        if !self.contains_pos(pos) {
+            Err(PosError::OutOfBounds(pos))
+        } else if !self.src.is_char_boundary((pos - self.start_pos).0 as usize)
+        {
+            Err(PosError::NotCharBoundary(pos))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Checks that the span is neither a dummy span nor reversed and that
+    /// both ends are valid positions (see `check_pos`). Spans passing this
+    /// check can be used to slice the source code.
+    pub fn check_span(&self, span: Span) -> Result<(), PosError> {
+        if span.is_dummy() {
+            Err(PosError::DummySpan)
+        } else if span.is_reversed() {
+            Err(PosError::ReversedSpan(span))
+        } else {
+            self.check_pos(span.lo)?;
+            self.check_pos(span.hi)
+        }
+    }
+
+    /// Returns the first char boundary after `pos` or `None` if `pos` is
+    /// not within this file or there is no char after `pos`. `pos` itself
+    /// doesn't need to be a char boundary.
+    pub fn next_char_boundary(&self, pos: BytePos) -> Option<BytePos> {
+        if !self.contains_pos(pos) || pos == self.end_pos() {
+            return None;
+        }
+
+        let local = (pos - self.start_pos).0 as usize;
+        let next = (local + 1..=self.src.len())
+            .find(|&i| self.src.is_char_boundary(i))
+            .unwrap_or(self.src.len());
+        Some(self.start_pos + BytePos(next as SrcOffset))
+    }
+
+    /// Returns the last char boundary before `pos` or `None` if `pos` is
+    /// not within this file or there is no char before `pos`. `pos` itself
+    /// doesn't need to be a char boundary.
+    pub fn prev_char_boundary(&self, pos: BytePos) -> Option<BytePos> {
+        if !self.contains_pos(pos) || pos == self.start_pos {
+            return None;
+        }
+
+        let local = (pos - self.start_pos).0 as usize;
+        let prev = (0..local).rev()
+            .find(|&i| self.src.is_char_boundary(i))
+            .unwrap_or(0);
+        Some(self.start_pos + BytePos(prev as SrcOffset))
+    }
+
+    /// Returns the span of the char starting at `pos`. Returns an error if
+    /// `pos` is not a char boundary or if there is no char at `pos` (e.g. at
+    /// the end of the file).
+    pub fn char_span_at(&self, pos: BytePos) -> Result<Span, PosError> {
+        self.span_of_chars(pos, 1)
+    }
+
+    /// Returns the span of the `n` chars starting at `pos`. Returns an error
+    /// if `pos` is not a char boundary or if there are less than `n` chars
+    /// after `pos` (`NotEnoughChars`).
+    pub fn span_of_chars(&self, pos: BytePos, n: usize)
+        -> Result<Span, PosError>
+    {
+        self.check_pos(pos)?;
+
+        // The n-th char boundary after `pos` (the end of the file is one,
+        // too) is the end of the span
+        let rest = &self.src[(pos - self.start_pos).0 as usize..];
+        let len = rest.char_indices()
+            .map(|(i, _)| i)
+            .chain(iter::once(rest.len()))
+            .nth(n)
+            .ok_or(PosError::NotEnoughChars { pos, n })?;
+
+        Ok(Span::new(pos, pos + BytePos(len as SrcOffset)))
+    }
+
+    /// Returns the source code the span points to or an error if the span
+    /// is invalid (see `check_span`).
+    pub fn span_to_snippet(&self, span: Span) -> Result<&str, PosError> {
+        self.check_span(span)?;
+        let lo = (span.lo - self.start_pos).0 as usize;
+        let hi = (span.hi - self.start_pos).0 as usize;
+        Ok(&self.src[lo..hi])
+    }
+
+    /// Like `get_loc`, but the column is measured in the given unit instead
+    /// of bytes. If `offset` points into the middle of a multibyte char, the
+    /// column of that char is returned. If it points into a line break (e.g.
+    /// between `'\r'` and `'\n'`), the column of the end of the line is
+    /// returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the offset doesn't lie within this file. See
+    /// `try_get_loc_in` for a non-panicking version.
+    pub fn get_loc_in(&self, offset: BytePos, unit: ColUnit) -> Loc {
+        self.try_get_loc_in(offset, unit).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Like `get_loc_in`, but returns an error if the offset doesn't lie
+    /// within this file.
+    pub fn try_get_loc_in(&self, offset: BytePos, unit: ColUnit)
+        -> Result<Loc, PosError>
+    {
+        let loc = self.try_get_physical_loc(offset)?;
+        let reported_line = self.reported_line(loc.line);
+
+        // We can unwrap, because `try_get_physical_loc` returns valid lines
+        // only
+        let line = self.get_line(loc.line).unwrap();
+        let byte_col = min(loc.col.0 as usize, line.len());
+        let reported = Loc {
+            line: reported_line,
+            col: ColIdx(byte_col as SrcOffset),
+        };
+
+        let is_visual = matches!(unit, ColUnit::Visual { .. });
+        if unit == ColUnit::Byte
+            || (!is_visual
+                && (self.is_ascii || line.as_bytes()[..byte_col].is_ascii()))
+        {
+            return Ok(reported);
+        }
+
+        let col = line.char_indices()
+            .take_while(|&(i, c)| i + c.len_utf8() <= byte_col)
+            .fold(0, |col, (_, c)| advance_col(col, c, unit));
+
+        Ok(Loc { line: reported_line, col: ColIdx(col) })
+    }
+
+    /// Returns the position of the given location, whose column is measured
+    /// in the given unit, or `None` if the location doesn't exist in this
+    /// file. See `try_get_pos_in` for details.
+    pub fn get_pos_in(&self, loc: Loc, unit: ColUnit) -> Option<BytePos> {
+        self.try_get_pos_in(loc, unit).ok()
+    }
 
-        Loc { line: line, col: ColIdx(col.0) }
+    /// Returns the position of the given location, whose column is measured
+    /// in the given unit. Like in `try_get_pos`, the line is a physical line.
+    /// Returns an error if the line doesn't exist, if the column is past the
+    /// end of the line or if it doesn't lie on a char boundary (e.g. between
+    /// two UTF-16 surrogates or within a wide char). A visual column never
+    /// separates a char from the zero-width chars (e.g. combining marks)
+    /// following it.
+    pub fn try_get_pos_in(&self, loc: Loc, unit: ColUnit)
+        -> Result<BytePos, PosError>
+    {
+        let is_visual = matches!(unit, ColUnit::Visual { .. });
+        let line = self.get_line(loc.line)
+            .ok_or(PosError::LineOutOfBounds(loc.line))?;
+        if unit == ColUnit::Byte
+            || (!is_visual && (self.is_ascii || line.is_ascii()))
+        {
+            return self.try_get_pos(loc);
+        }
+
+        // Visually, zero-width chars (e.g. combining marks) belong to the
+        // char before them, so the position is moved behind them
+        let is_attached = |i: usize, c: char| {
+            is_visual && i > 0 && !c.is_control() && width::char_width(c) == 0
+        };
+
+        let col = loc.col.0;
+        let mut units = 0;
+        let mut found = None;
+        for (i, c) in line.char_indices() {
+            if units >= col && !is_attached(i, c) {
+                found = Some(i);
+                break;
+            }
+            units = advance_col(units, c, unit);
+        }
+        let byte_col = match found {
+            Some(i) if units == col => i,
+            None if units == col => line.len(),
+            None if units < col => return Err(PosError::ColPastEndOfLine(loc)),
+            _ => return Err(PosError::ColNotOnCharBoundary(loc)),
+        };
+
+        // We can unwrap, because the line exists
+        Ok(self.get_line_start(loc.line).unwrap()
+            + BytePos(byte_col as SrcOffset))
+    }
+
+    /// Returns the position of the given location or `None` if the location
+    /// doesn't exist in this file. See `try_get_pos` for details.
+    pub fn get_pos(&self, loc: Loc) -> Option<BytePos> {
+        self.try_get_pos(loc).ok()
+    }
+
+    /// Returns the position of the given location. This is the inverse of
+    /// `get_physical_loc`: the line is a physical line and line directives
+    /// are ignored, so a location returned by `get_loc` can only be passed
+    /// here if the file has no directives. The column may point directly
+    /// after the last char of the line (before the line break), but not any
+    /// further.
+    pub fn try_get_pos(&self, loc: Loc) -> Result<BytePos, PosError> {
+        let line = self.get_line(loc.line)
+            .ok_or(PosError::LineOutOfBounds(loc.line))?;
+        let col = loc.col.0 as usize;
+
+        if col > line.len() {
+            Err(PosError::ColPastEndOfLine(loc))
+        } else if !line.is_char_boundary(col) {
+            Err(PosError::ColNotOnCharBoundary(loc))
+        } else {
+            // We can unwrap, because the line exists
+            Ok(self.get_line_start(loc.line).unwrap() + BytePos(loc.col.0))
+        }
+    }
+
+    /// Returns the span from location `lo` (inclusive) to location `hi`
+    /// (exclusive). Both locations are checked as described in `try_get_pos`.
+    pub fn get_span(&self, lo: Loc, hi: Loc) -> Result<Span, PosError> {
+        let lo_pos = self.try_get_pos(lo)?;
+        let hi_pos = self.try_get_pos(hi)?;
+
+        if hi_pos < lo_pos {
+            Err(PosError::ReversedRange { lo, hi })
+        } else {
+            Ok(Span::new(lo_pos, hi_pos))
+        }
+    }
+
+    /// Returns the span without leading and trailing whitespace. If the span
+    /// contains only whitespace, the empty span at its start is returned.
+    /// Dummy spans are returned unchanged.
+    pub fn trim_span(&self, span: Span) -> Result<Span, PosError> {
+        if span.is_dummy() {
+            return Ok(span);
+        }
+
+        let snippet = self.span_to_snippet(span)?;
+        let trimmed = snippet.trim_start();
+        if trimmed.is_empty() {
+            return Ok(span.shrink_to_lo());
+        }
+        let leading = (snippet.len() - trimmed.len()) as SrcOffset;
+        let trailing = (trimmed.len() - trimmed.trim_end().len()) as SrcOffset;
+
+        Ok(Span {
+            lo: span.lo + BytePos(leading),
+            hi: span.hi - BytePos(trailing),
+            ctxt: span.ctxt,
+        })
+    }
+
+    /// Extends the span to the start of the line its start lies in. Dummy
+    /// spans are returned unchanged.
+    pub fn extend_to_line_start(&self, span: Span) -> Result<Span, PosError> {
+        if span.is_dummy() {
+            return Ok(span);
+        }
+
+        self.check_span(span)?;
+        let line = self.get_line_idx(span.lo);
+        // We can unwrap, because the line exists
+        Ok(span.with_lo(self.get_line_start(line).unwrap()))
+    }
+
+    /// Extends the span to the end of the line its end lies in. The line
+    /// break is not included. Dummy spans are returned unchanged.
+    pub fn extend_to_line_end(&self, span: Span) -> Result<Span, PosError> {
+        if span.is_dummy() {
+            return Ok(span);
+        }
+
+        self.check_span(span)?;
+        let line = self.get_line_idx(span.hi);
+        // We can unwrap, because the line exists
+        let start = self.get_line_start(line).unwrap();
+        let len = self.get_line(line).unwrap().len() as SrcOffset;
+        Ok(span.with_hi(max(span.hi, start + BytePos(len))))
     }
 
     /// Returns the line with the given index or `None` if it is invalid.
+    ///
+    /// The line break at the end of the line is not included.
     pub fn get_line(&self, line: LineIdx) -> Option<&str> {
-        self.lines.borrow().get(line.0 as usize).map(|&BytePos(start)| {
-            let end = self.src[start as usize..]
-                          .find("\n")
-                          .unwrap_or(self.src.len() - start as usize);
-            &self.src[start as usize .. (end + start as usize)]
+        let idx = line.0 as usize;
+        self.line_table().get(idx).map(|&BytePos(start)| {
+            let end = self.line_table()
+                .get(idx + 1)
+                .map_or(self.src.len(), |&BytePos(end)| end as usize);
+            let line = &self.src[start as usize..end];
+
+            line.strip_suffix("\r\n")
+                .or_else(|| line.strip_suffix('\n'))
+                .or_else(|| {
+                    line.strip_suffix('\r').filter(|_| self.cr_line_breaks)
+                })
+                .unwrap_or(line)
         })
     }
 
+    /// Returns which line endings are used in this file or `None` if there
+    /// are no line breaks at all. Lone `'\r'` are always detected as `Cr`,
+    /// even if they are not treated as line breaks (see
+    /// `with_cr_line_breaks`), so that callers can decide whether to enable
+    /// that.
+    pub fn line_ending(&self) -> Option<LineEnding> {
+        let bytes = self.src.as_bytes();
+        let mut found = None;
+        for (i, &b) in bytes.iter().enumerate() {
+            let ending = match b {
+                b'\n' if i > 0 && bytes[i - 1] == b'\r' => LineEnding::CrLf,
+                b'\n' => LineEnding::Lf,
+                b'\r' if bytes.get(i + 1) != Some(&b'\n') => LineEnding::Cr,
+                _ => continue,
+            };
+
+            match found {
+                None => found = Some(ending),
+                Some(e) if e == ending => {}
+                Some(_) => return Some(LineEnding::Mixed),
+            }
+        }
+        found
+    }
+
+    /// Sets whether a lone `'\r'` (not followed by `'\n'`) is treated as
+    /// line break, as it was common on classic Mac OS. By default only
+    /// `'\n'` and `"\r\n"` are line breaks.
+    pub fn with_cr_line_breaks(mut self, enabled: bool) -> FileMap {
+        if self.cr_line_breaks != enabled {
+            self.cr_line_breaks = enabled;
+            self.lines = OnceLock::new();
+        }
+        self
+    }
+
+    /// Records that this file was loaded by the include directive at the
+    /// given span (usually in another file of the same `CodeMap`).
+    /// Diagnostics pointing into this file then show the chain of includes.
+    pub fn with_included_from(mut self, span: Span) -> FileMap {
+        self.included_from = Some(span);
+        self
+    }
+
+    /// Returns the span of the include directive which loaded this file, if
+    /// any.
+    pub fn included_from(&self) -> Option<Span> {
+        self.included_from
+    }
+
     /// Returns the byte offset of the first symbol in `line`
     pub fn get_line_start(&self, line: LineIdx) -> Option<BytePos> {
-        self.lines.borrow().get(line.0 as usize).map(|&pos| pos)
+        self.line_table()
+            .get(line.0 as usize)
+            .map(|&pos| self.start_pos + pos)
     }
 
-    /// Searches for line endings and collects all line beginnings in the
-    /// source string. It starts searching at the latest line beginning in the
-    /// list so far (or at the beginning of none was added yet).
-    ///
-    /// Normally this is done while lexing to avoid iterating
-    /// over the whole string multiple times. Mostly handy for tests.
+    /// Returns the span of the given line without its line break or `None`
+    /// if the line index is invalid.
+    pub fn line_span(&self, line: LineIdx) -> Option<Span> {
+        let start = self.get_line_start(line)?;
+        let len = self.get_line(line)?.len() as SrcOffset;
+        Some(Span::new(start, start + BytePos(len)))
+    }
+
+    /// Like `line_span`, but the line break is included.
+    pub fn line_span_with_terminator(&self, line: LineIdx) -> Option<Span> {
+        let start = self.get_line_start(line)?;
+        let end = self.get_line_start(line + LineIdx(1))
+            .unwrap_or_else(|| self.end_pos());
+        Some(Span::new(start, end))
+    }
+
+    /// Returns an iterator over all lines of this file together with their
+    /// indices. The line breaks are not included.
+    pub fn lines(&self) -> Lines<'_> {
+        Lines::new(self)
+    }
+
+    /// Returns a cursor at the start of this file, which can be used to lex
+    /// the source.
+    pub fn cursor(&self) -> Cursor<'_> {
+        Cursor::new(self)
+    }
+
+    /// Returns an iterator over all lines the given span covers, together
+    /// with the covered column range on each line. The line containing
+    /// `span.hi` is always included, even if the span ends right at its
+    /// start. Returns an error if the span is invalid (see `check_span`).
+    pub fn span_lines(&self, span: Span) -> Result<SpanLines<'_>, PosError> {
+        self.check_span(span)?;
+        Ok(SpanLines::new(
+            self,
+            self.get_physical_loc(span.lo),
+            self.get_physical_loc(span.hi),
+        ))
+    }
+
+    /// Computes the line table if that didn't happen yet. This is never
+    /// required, since the line table is computed on the first lookup
+    /// automatically. However, it can be useful to avoid doing it while
+    /// other threads are waiting.
     pub fn find_lines(&self) {
-        // We can unwrap here, because the vector contains at least one element
-        let last_line_so_far = self.lines.borrow().last().unwrap().0 as usize;
-        for (pos, c) in self.src[last_line_so_far..].char_indices() {
-            // it doesn't matter if there was a '\n' or '\r\n'
-            if c == '\n' {
-                let line_start = (pos + c.len_utf8()) as SrcOffset;
-                self.lines.borrow_mut().push(BytePos(line_start));
+        self.line_table();
+    }
+
+    /// Returns the line table, computing it if necessary.
+    fn line_table(&self) -> &[BytePos] {
+        self.lines.get_or_init(|| {
+            let mut lines = vec![BytePos(0)];
+            lines.extend(self.find_line_starts(0, self.src.len()));
+            lines
+        })
+    }
+
+    /// Returns all line beginnings `s` with `from <= s <= to` (the first line
+    /// beginning at 0 is never returned). Whether `s` is a line beginning
+    /// only depends on the bytes at `s - 1` and `s`.
+    fn find_line_starts(&self, from: usize, to: usize)
+        -> impl Iterator<Item = BytePos> + '_
+    {
+        let bytes = self.src.as_bytes();
+        (max(from, 1)..min(to, bytes.len()) + 1).filter(move |&s| {
+            match bytes[s - 1] {
+                b'\n' => true,
+                b'\r' => self.cr_line_breaks && bytes.get(s) != Some(&b'\n'),
+                _ => false,
+            }
+        }).map(|s| BytePos(s as SrcOffset))
+    }
+
+    /// Replaces the text in `span` with `text`. The line table is updated
+    /// incrementally and the returned `Edit` can be used to map spans
+    /// obtained before the edit (see `Edit::map_span`).
+    ///
+    /// Files which are part of a `CodeMap` are shared and can't be edited,
+    /// since changing the length would overlap with the position range of
+    /// the following file.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the span is a dummy span, is reversed, is not within this
+    /// file or doesn't start and end on char boundaries.
+    pub fn edit(&mut self, span: Span, text: &str) -> Edit {
+        assert!(!span.is_dummy(), "cannot edit a dummy span");
+        assert!(!span.is_reversed(), "cannot edit reversed span {:?}", span);
+        assert!(self.contains_pos(span.lo) && self.contains_pos(span.hi),
+            "edited span {:?} is not within the file", span);
+
+        let lo = (span.lo - self.start_pos).0;
+        let hi = (span.hi - self.start_pos).0;
+        self.src.replace_range(lo as usize..hi as usize, text);
+        self.is_ascii = self.is_ascii && text.is_ascii();
+
+        let edit = Edit { span, new_len: text.len() as SrcOffset };
+
+        // Origins and replaced bytes overlapping the edit are not valid
+        // anymore, the ones behind it are shifted
+        let local_edit = Edit {
+            span: Span::new(BytePos(lo), BytePos(hi)),
+            ..edit
+        };
+        self.origins.retain_mut(|o| {
+            if o.generated.hi <= local_edit.span.lo {
+                true
+            } else if o.generated.lo >= local_edit.span.hi {
+                // We can unwrap, because the span is behind the edit
+                o.generated = local_edit.map_span(o.generated).unwrap();
+                true
+            } else {
+                false
+            }
+        });
+        let replacement_len = '\u{FFFD}'.len_utf8() as SrcOffset;
+        self.replacements.retain_mut(|r| {
+            let replaced = Span::new(r.0, r.0 + BytePos(replacement_len));
+            if replaced.hi <= local_edit.span.lo {
+                true
+            } else if replaced.lo >= local_edit.span.hi {
+                // We can unwrap, because the span is behind the edit
+                r.0 = local_edit.map_pos(r.0).unwrap();
+                true
+            } else {
+                false
+            }
+        });
+        self.line_directives.retain_mut(|d| {
+            match local_edit.map_pos(d.pos) {
+                Some(pos) => {
+                    d.pos = pos;
+                    true
+                }
+                None => false,
             }
+        });
+
+        // If the line table wasn't computed yet, it will be computed from the
+        // new source on the first lookup.
+        if self.lines.get().is_none() {
+            return edit;
+        }
+
+        // All line starts depending on a changed byte (the ones in `lo..=hi`)
+        // are searched again in the new source. The ones behind the edit are
+        // shifted. The first line start is always kept.
+        let new_hi = (lo + edit.new_len) as usize;
+        let new_lines: Vec<_> = self.find_line_starts(lo as usize, new_hi)
+            .collect();
+
+        // We can unwrap, because we checked above
+        let lines = self.lines.get_mut().unwrap();
+        let first = lines.partition_point(|&l| l.0 < max(lo, 1));
+        let after = lines.partition_point(|&l| l.0 <= hi);
+        let shifted: Vec<_> = lines[after..].iter()
+            .map(|&l| BytePos((l.0 as i64 + edit.delta()) as SrcOffset))
+            .collect();
+        lines.truncate(first);
+        lines.extend(new_lines);
+        lines.extend(shifted);
+
+        edit
+    }
+}
+
+// `FileMap`s are serialized with their source, but without the line table,
+// which is computed lazily after deserializing.
+#[cfg(feature = "serde")]
+mod serde_impl {
+    use super::FileMap;
+    use code::{BytePos, Encoding, FileName, LineDirective, Origin, Span};
+    use code::SrcOffset;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::sync::OnceLock;
+
+    #[derive(Serialize)]
+    struct FileMapRef<'a> {
+        filename: &'a FileName,
+        start_pos: BytePos,
+        encoding: Encoding,
+        bom: bool,
+        replacements: &'a [(BytePos, SrcOffset)],
+        cr_line_breaks: bool,
+        src: &'a str,
+        origins: &'a [Origin],
+        line_directives: &'a [LineDirective],
+        included_from: Option<Span>,
+    }
+
+    #[derive(Deserialize)]
+    struct FileMapData {
+        filename: FileName,
+        start_pos: BytePos,
+        encoding: Encoding,
+        bom: bool,
+        #[serde(default)]
+        replacements: Vec<(BytePos, SrcOffset)>,
+        cr_line_breaks: bool,
+        src: String,
+        #[serde(default)]
+        origins: Vec<Origin>,
+        #[serde(default)]
+        line_directives: Vec<LineDirective>,
+        #[serde(default)]
+        included_from: Option<Span>,
+    }
+
+    impl Serialize for FileMap {
+        fn serialize<S: Serializer>(&self, serializer: S)
+            -> Result<S::Ok, S::Error>
+        {
+            FileMapRef {
+                filename: &self.filename,
+                start_pos: self.start_pos,
+                encoding: self.encoding,
+                bom: self.bom,
+                replacements: &self.replacements,
+                cr_line_breaks: self.cr_line_breaks,
+                src: &self.src,
+                origins: &self.origins,
+                line_directives: &self.line_directives,
+                included_from: self.included_from,
+            }.serialize(serializer)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for FileMap {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D)
+            -> Result<FileMap, D::Error>
+        {
+            let data = FileMapData::deserialize(deserializer)?;
+            Ok(FileMap {
+                filename: data.filename,
+                is_ascii: data.src.is_ascii(),
+                src: data.src,
+                encoding: data.encoding,
+                bom: data.bom,
+                replacements: data.replacements,
+                cr_line_breaks: data.cr_line_breaks,
+                start_pos: data.start_pos,
+                lines: OnceLock::new(),
+                origins: data.origins,
+                line_directives: data.line_directives,
+                included_from: data.included_from,
+            })
+        }
+    }
+}
+
+/// Returns the column (in the given unit) after the char `c` starting at
+/// column `col`.
+fn advance_col(col: SrcOffset, c: char, unit: ColUnit) -> SrcOffset {
+    match unit {
+        ColUnit::Byte => col + c.len_utf8() as SrcOffset,
+        ColUnit::Char => col + 1,
+        ColUnit::Utf16 => col + c.len_utf16() as SrcOffset,
+        ColUnit::Visual { tab_width } => {
+            width::advance_visual_col(col as usize, c, tab_width as usize)
+                as SrcOffset
         }
     }
 }
@@ -117,8 +1038,12 @@ impl fmt::Debug for FileMap {
 
         f.debug_struct("FileMap")
             .field("filename", &self.filename)
+            .field("start_pos", &self.start_pos.0)
             .field("src", &format!("<long string> (len {})", self.src.len()))
-            .field("lines", &Dummy(&self.lines.borrow()))
+            .field("lines", &Dummy(self.line_table()))
+            .field("origins", &self.origins)
+            .field("line_directives", &self.line_directives)
+            .field("included_from", &self.included_from)
             .finish()
     }
 }
@@ -127,12 +1052,10 @@ impl fmt::Debug for FileMap {
 #[test]
 fn location_lookup() {
     let map = FileMap::new("<dummy>", "foo\r\nbär\nbaz");
-    map.find_lines();
 
     // "foo\r\n" is 5 bytes. "bär\n" is 5 bytes, too.
-    assert_eq!(*map.lines.borrow(),
-        vec![BytePos(0), BytePos(5), BytePos(10)]
-    );
+    assert_eq!(map.line_table(), [BytePos(0), BytePos(5), BytePos(10)]);
+    assert_eq!(map.get_line(LineIdx(0)), Some("foo"));
 
     macro_rules! is_at {
         ($offset:expr => [$line:expr, $col:expr]) => {
@@ -150,3 +1073,424 @@ fn location_lookup() {
     is_at!(10 => [2, 0]);
     is_at!(12 => [2, 2]);
 }
+
+#[test]
+fn column_units() {
+    let map = FileMap::new("<dummy>", "foo\r\nbär\na😀b\n");
+
+    let loc = |line, col| Loc { line: LineIdx(line), col: ColIdx(col) };
+
+    // "bär": 'r' is at byte col 3, but at char col 2
+    assert_eq!(map.get_loc_in(BytePos(8), ColUnit::Byte), loc(1, 3));
+    assert_eq!(map.get_loc_in(BytePos(8), ColUnit::Char), loc(1, 2));
+    assert_eq!(map.get_loc_in(BytePos(8), ColUnit::Utf16), loc(1, 2));
+    assert_eq!(map.get_loc_in(BytePos(9), ColUnit::Char), loc(1, 3));
+    // inside of 'ä'
+    assert_eq!(map.get_loc_in(BytePos(7), ColUnit::Char), loc(1, 1));
+
+    // "a😀b": the emoji is 4 bytes and two UTF-16 code units
+    assert_eq!(map.get_loc_in(BytePos(15), ColUnit::Byte), loc(2, 5));
+    assert_eq!(map.get_loc_in(BytePos(15), ColUnit::Char), loc(2, 2));
+    assert_eq!(map.get_loc_in(BytePos(15), ColUnit::Utf16), loc(2, 3));
+    assert_eq!(map.try_get_loc_in(BytePos(100), ColUnit::Char),
+        Err(PosError::OutOfBounds(BytePos(100))));
+
+    // inside of a "\r\n" line break
+    let crlf = FileMap::new("<dummy>", "foo\r\nä\r\n");
+    for &unit in &[ColUnit::Byte, ColUnit::Char, ColUnit::Utf16] {
+        assert_eq!(crlf.get_loc_in(BytePos(4), unit), loc(0, 3));
+        assert_eq!(crlf.get_pos_in(loc(0, 3), unit), Some(BytePos(3)));
+    }
+    assert_eq!(crlf.get_loc_in(BytePos(8), ColUnit::Char), loc(1, 1));
+
+    // ASCII lines
+    assert_eq!(map.get_loc_in(BytePos(2), ColUnit::Utf16), loc(0, 2));
+    assert_eq!(map.get_pos_in(loc(0, 2), ColUnit::Utf16), Some(BytePos(2)));
+
+    // and back again
+    assert_eq!(map.get_pos_in(loc(1, 2), ColUnit::Char), Some(BytePos(8)));
+    assert_eq!(map.get_pos_in(loc(1, 3), ColUnit::Char), Some(BytePos(9)));
+    assert_eq!(map.get_pos_in(loc(1, 4), ColUnit::Char), None);
+    assert_eq!(map.get_pos_in(loc(1, 3), ColUnit::Byte), Some(BytePos(8)));
+    assert_eq!(map.get_pos_in(loc(1, 2), ColUnit::Byte), None);
+    assert_eq!(map.get_pos_in(loc(2, 3), ColUnit::Utf16), Some(BytePos(15)));
+    assert_eq!(map.get_pos_in(loc(2, 2), ColUnit::Utf16), None);
+    assert_eq!(map.get_pos_in(loc(2, 4), ColUnit::Utf16), Some(BytePos(16)));
+    assert_eq!(map.get_pos_in(loc(7, 0), ColUnit::Char), None);
+    assert_eq!(map.try_get_pos_in(loc(1, 4), ColUnit::Char),
+        Err(PosError::ColPastEndOfLine(loc(1, 4))));
+    assert_eq!(map.try_get_pos_in(loc(2, 2), ColUnit::Utf16),
+        Err(PosError::ColNotOnCharBoundary(loc(2, 2))));
+    assert_eq!(map.try_get_pos_in(loc(7, 0), ColUnit::Char),
+        Err(PosError::LineOutOfBounds(LineIdx(7))));
+}
+
+#[test]
+fn position_lookup() {
+    let map = FileMap::new("<dummy>", "foo\nbär\n\nbaz");
+
+    let loc = |line, col| Loc { line: LineIdx(line), col: ColIdx(col) };
+
+    // every position maps back to itself
+    for offset in 0..map.src().len() as SrcOffset + 1 {
+        if map.src().is_char_boundary(offset as usize) {
+            let pos = BytePos(offset);
+            assert_eq!(map.get_pos(map.get_loc(pos)), Some(pos));
+        }
+    }
+
+    assert_eq!(map.get_pos(loc(0, 3)), Some(BytePos(3)));
+    assert_eq!(map.get_pos(loc(2, 0)), Some(BytePos(9)));
+    assert_eq!(map.try_get_pos(loc(0, 4)), Err(PosError::ColPastEndOfLine(loc(0, 4))));
+    assert_eq!(map.try_get_pos(loc(1, 2)), Err(PosError::ColNotOnCharBoundary(loc(1, 2))));
+    assert_eq!(map.try_get_pos(loc(4, 0)), Err(PosError::LineOutOfBounds(LineIdx(4))));
+
+    assert_eq!(map.get_span(loc(0, 1), loc(1, 1)), Ok(Span::new(BytePos(1), BytePos(5))));
+    assert_eq!(map.get_span(loc(3, 1), loc(3, 1)), Ok(Span::empty_at(BytePos(11))));
+    assert_eq!(
+        map.get_span(loc(1, 1), loc(0, 1)),
+        Err(PosError::ReversedRange { lo: loc(1, 1), hi: loc(0, 1) })
+    );
+}
+
+#[test]
+fn visual_columns() {
+    let map = FileMap::new("<dummy>", "\tx\nab\ty\n日本z\ne\u{301}w");
+
+    let unit = ColUnit::Visual { tab_width: 4 };
+    let loc = |line, col| Loc { line: LineIdx(line), col: ColIdx(col) };
+    let pos_of = |s: &str| BytePos(map.src().find(s).unwrap() as SrcOffset);
+
+    assert_eq!(map.get_loc_in(pos_of("x"), unit), loc(0, 4));
+    assert_eq!(map.get_loc_in(pos_of("y"), unit), loc(1, 4));
+    assert_eq!(map.get_loc_in(pos_of("z"), unit), loc(2, 4));
+    assert_eq!(map.get_loc_in(pos_of("w"), unit), loc(3, 1));
+
+    assert_eq!(map.get_pos_in(loc(1, 4), unit), Some(pos_of("y")));
+    assert_eq!(map.get_pos_in(loc(2, 2), unit), Some(pos_of("本")));
+    // behind the combining mark of "é"
+    assert_eq!(map.get_pos_in(loc(3, 1), unit), Some(pos_of("w")));
+    assert_eq!(map.get_pos_in(loc(3, 2), unit), Some(map.end_pos()));
+    // inside of the tab and inside of a wide char
+    assert_eq!(map.get_pos_in(loc(1, 3), unit), None);
+    assert_eq!(map.get_pos_in(loc(2, 1), unit), None);
+}
+
+#[test]
+fn edits() {
+    let mut map = FileMap::new("<dummy>", "foo\nbar\nbaz\n");
+    map.find_lines();
+
+    let s = |lo, hi| Span::new(BytePos(lo), BytePos(hi));
+    let lines = |map: &FileMap| {
+        (0..map.num_lines() as SrcOffset)
+            .map(|l| map.get_line(LineIdx(l)).unwrap().to_string())
+            .collect::<Vec<_>>()
+    };
+
+    // merge the first two lines
+    let edit = map.edit(s(2, 5), "x");
+    assert_eq!(map.src(), "foxar\nbaz\n");
+    assert_eq!(lines(&map), vec!["foxar", "baz", ""]);
+    assert_eq!(edit.map_span(s(8, 11)), Some(s(6, 9)));
+
+    // split a line
+    map.edit(s(6, 6), "q\nu\n");
+    assert_eq!(lines(&map), vec!["foxar", "q", "u", "baz", ""]);
+
+    // replace everything
+    let all = map.span();
+    map.edit(all, "a\nb");
+    assert_eq!(lines(&map), vec!["a", "b"]);
+    assert_eq!(map.line_table(), [BytePos(0), BytePos(2)]);
+
+    // the result equals a freshly indexed file
+    let fresh = FileMap::new("<dummy>", map.src());
+    assert_eq!(map.line_table(), fresh.line_table());
+}
+
+#[test]
+#[should_panic(expected = "cannot edit reversed span")]
+fn reversed_edit() {
+    let mut map = FileMap::new("<dummy>", "abcdef");
+    map.edit(Span::new(BytePos(4), BytePos(2)), "x");
+}
+
+#[test]
+fn shared_between_threads() {
+    use std::sync::Arc;
+    use std::thread;
+
+    let map = Arc::new(FileMap::new("<dummy>", "a\nb\nc\nd"));
+    let handles: Vec<_> = (0..4).map(|i| {
+        let map = map.clone();
+        thread::spawn(move || map.get_loc(BytePos(i * 2)))
+    }).collect();
+
+    for (i, h) in handles.into_iter().enumerate() {
+        let loc = h.join().unwrap();
+        assert_eq!(loc, Loc { line: LineIdx(i as SrcOffset), col: ColIdx(0) });
+    }
+    assert_eq!(map.num_lines(), 4);
+}
+
+#[test]
+fn line_endings() {
+    let lines = |map: &FileMap| {
+        (0..map.num_lines() as SrcOffset)
+            .map(|l| map.get_line(LineIdx(l)).unwrap().to_string())
+            .collect::<Vec<_>>()
+    };
+
+    let map = FileMap::new("<dummy>", "a\r\nb\r\n");
+    assert_eq!(map.line_ending(), Some(LineEnding::CrLf));
+    assert_eq!(lines(&map), vec!["a", "b", ""]);
+
+    let map = FileMap::new("<dummy>", "a\rb\r\nc\nd");
+    assert_eq!(map.line_ending(), Some(LineEnding::Mixed));
+    assert_eq!(lines(&map), vec!["a\rb", "c", "d"]);
+
+    let map = map.with_cr_line_breaks(true);
+    assert_eq!(map.line_ending(), Some(LineEnding::Mixed));
+    assert_eq!(lines(&map), vec!["a", "b", "c", "d"]);
+
+    let map = FileMap::new("<dummy>", "a\rb\r").with_cr_line_breaks(true);
+    assert_eq!(map.line_ending(), Some(LineEnding::Cr));
+    assert_eq!(lines(&map), vec!["a", "b", ""]);
+
+    let map = FileMap::new("<dummy>", "a\rb");
+    assert_eq!(map.line_ending(), Some(LineEnding::Cr));
+    assert_eq!(lines(&map), vec!["a\rb"]);
+
+    assert_eq!(FileMap::new("<dummy>", "a\nb").line_ending(), Some(LineEnding::Lf));
+    assert_eq!(FileMap::new("<dummy>", "ab").line_ending(), None);
+
+    // edits which join or split "\r\n"
+    let mut map = FileMap::new("<dummy>", "a\rxb\nc").with_cr_line_breaks(true);
+    assert_eq!(lines(&map), vec!["a", "xb", "c"]);
+    map.edit(Span::new(BytePos(2), BytePos(4)), "\n");
+    assert_eq!(map.src(), "a\r\n\nc");
+    assert_eq!(lines(&map), vec!["a", "", "c"]);
+    map.edit(Span::new(BytePos(2), BytePos(2)), "y");
+    assert_eq!(lines(&map), vec!["a", "y", "", "c"]);
+
+    let fresh = FileMap::new("<dummy>", map.src()).with_cr_line_breaks(true);
+    assert_eq!(map.line_table(), fresh.line_table());
+}
+
+#[test]
+fn fallible_lookups() {
+    let mut codemap = ::code::CodeMap::new();
+    codemap.add_file("<a>", "xyz");
+    let map = codemap.add_file("<b>", "foo\nbär");
+
+    let s = |lo, hi| Span::new(BytePos(lo), BytePos(hi));
+
+    assert_eq!(map.try_get_loc(BytePos(2)), Err(PosError::OutOfBounds(BytePos(2))));
+    assert_eq!(map.try_get_loc(BytePos(20)), Err(PosError::OutOfBounds(BytePos(20))));
+    assert_eq!(
+        map.try_get_loc(BytePos(9)),
+        Ok(Loc { line: LineIdx(1), col: ColIdx(1) })
+    );
+
+    assert_eq!(map.span_to_snippet(s(4, 7)), Ok("foo"));
+    assert_eq!(map.span_to_snippet(s(8, 12)), Ok("bär"));
+    assert_eq!(map.span_to_snippet(s(8, 10)), Err(PosError::NotCharBoundary(BytePos(10))));
+    assert_eq!(map.span_to_snippet(s(7, 5)), Err(PosError::ReversedSpan(s(7, 5))));
+    assert_eq!(map.span_to_snippet(Span::dummy()), Err(PosError::DummySpan));
+    assert_eq!(map.span_to_snippet(s(2, 5)), Err(PosError::OutOfBounds(BytePos(2))));
+}
+
+#[test]
+fn source_aware_span_ops() {
+    let map = FileMap::new("<dummy>", "  foo bar \r\n\tbaz  \n");
+    let s = |lo, hi| Span::new(BytePos(lo), BytePos(hi));
+
+    assert_eq!(map.trim_span(s(0, 10)), Ok(s(2, 9)));
+    assert_eq!(map.trim_span(s(6, 17)), Ok(s(6, 16)));
+    assert_eq!(map.trim_span(s(9, 13)), Ok(s(9, 9)));
+    assert_eq!(map.trim_span(Span::dummy()), Ok(Span::dummy()));
+    assert_eq!(map.trim_span(s(5, 2)), Err(PosError::ReversedSpan(s(5, 2))));
+
+    assert_eq!(map.extend_to_line_end(s(2, 5)), Ok(s(2, 10)));
+    assert_eq!(map.extend_to_line_end(s(2, 14)), Ok(s(2, 18)));
+    assert_eq!(map.extend_to_line_start(s(14, 17)), Ok(s(12, 17)));
+    assert_eq!(map.extend_to_line_start(s(2, 5)), Ok(s(0, 5)));
+}
+
+#[test]
+fn line_iteration() {
+    let map = FileMap::new("<dummy>", "ab\r\n\ncde\nf");
+    let s = |lo, hi| Span::new(BytePos(lo), BytePos(hi));
+
+    let lines: Vec<_> = map.lines().collect();
+    assert_eq!(lines, [
+        (LineIdx(0), "ab"),
+        (LineIdx(1), ""),
+        (LineIdx(2), "cde"),
+        (LineIdx(3), "f"),
+    ]);
+    assert_eq!(map.lines().len(), 4);
+    assert_eq!(map.lines().next_back(), Some((LineIdx(3), "f")));
+
+    assert_eq!(map.line_span(LineIdx(0)), Some(s(0, 2)));
+    assert_eq!(map.line_span_with_terminator(LineIdx(0)), Some(s(0, 4)));
+    assert_eq!(map.line_span(LineIdx(1)), Some(s(4, 4)));
+    assert_eq!(map.line_span_with_terminator(LineIdx(1)), Some(s(4, 5)));
+    assert_eq!(map.line_span(LineIdx(3)), Some(s(9, 10)));
+    assert_eq!(map.line_span_with_terminator(LineIdx(3)), Some(s(9, 10)));
+    assert_eq!(map.line_span(LineIdx(4)), None);
+    assert_eq!(map.line_span_with_terminator(LineIdx(4)), None);
+
+    let covered = |span| {
+        map.span_lines(span).unwrap()
+            .map(|l| (l.line.0, l.start.0, l.end.0, l.covered()))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(covered(s(1, 2)), [(0, 1, 2, "b")]);
+    assert_eq!(covered(s(1, 7)), [(0, 1, 2, "b"), (1, 0, 0, ""), (2, 0, 2, "cd")]);
+    assert_eq!(covered(s(2, 9)), [(0, 2, 2, ""), (1, 0, 0, ""), (2, 0, 3, "cde"), (3, 0, 0, "")]);
+    assert_eq!(covered(s(3, 3)), [(0, 2, 2, "")]);
+
+    let line = map.span_lines(s(6, 7)).unwrap().next().unwrap();
+    assert_eq!((line.before(), line.covered(), line.after()), ("c", "d", "e"));
+    assert_eq!(map.span_lines(s(3, 1)).err(), Some(PosError::ReversedSpan(s(3, 1))));
+}
+
+#[test]
+fn origins() {
+    let s = |lo, hi| Span::new(BytePos(lo), BytePos(hi));
+
+    let mut map = FileMap::new("<generated>", "aaa bbbbbb ccc");
+    map.add_origin(Origin::generated(s(4, 10), s(103, 106)));
+    map.add_origin(Origin::copied(s(0, 4), BytePos(100)));
+    map.add_origin(Origin::copied(s(10, 14), BytePos(106)));
+    assert_eq!(map.origins().count(), 3);
+
+    assert_eq!(map.original_span(s(1, 3)), Some(s(101, 103)));
+    assert_eq!(map.original_span(s(5, 7)), Some(s(103, 106)));
+    assert_eq!(map.original_span(s(11, 14)), Some(s(107, 110)));
+    assert_eq!(map.original_span(s(2, 6)), None);
+    assert_eq!(map.original_span(Span::dummy()), None);
+    assert_eq!(map.origin_of(s(4, 4)).map(|o| o.verbatim), Some(false));
+
+    // Origins are moved with the file and updated on edits
+    map.set_start_pos(BytePos(50));
+    assert_eq!(map.original_span(s(51, 53)), Some(s(101, 103)));
+    map.edit(s(56, 57), "");
+    assert_eq!(map.origins().count(), 2);
+    assert_eq!(map.original_span(s(55, 57)), None);
+    assert_eq!(map.original_span(s(60, 63)), Some(s(107, 110)));
+}
+
+#[test]
+#[should_panic(expected = "overlaps with another origin")]
+fn overlapping_origins() {
+    let s = |lo, hi| Span::new(BytePos(lo), BytePos(hi));
+
+    let mut map = FileMap::new("<generated>", "aaa bbbbbb ccc");
+    map.add_origin(Origin::copied(s(0, 4), BytePos(100)));
+    map.add_origin(Origin::copied(s(3, 5), BytePos(100)));
+}
+
+#[test]
+fn line_directives() {
+    let loc = |line, col| Loc { line: LineIdx(line), col: ColIdx(col) };
+
+    let mut map = FileMap::new("out.xs", "a\nb\ncc\nd\ne\n");
+    map.add_line_directive(LineDirective::new(BytePos(5), LineIdx(40)));
+    map.add_line_directive(
+        LineDirective::new(BytePos(8), LineIdx(9)).with_filename("tmpl.xs")
+    );
+
+    assert_eq!(map.get_loc(BytePos(2)), loc(1, 0));
+    assert_eq!(map.get_loc(BytePos(4)), loc(40, 0));
+    assert_eq!(map.get_loc(BytePos(5)), loc(40, 1));
+    assert_eq!(map.get_loc(BytePos(7)), loc(9, 0));
+    assert_eq!(map.get_loc(BytePos(9)), loc(10, 0));
+    assert_eq!(map.get_loc(BytePos(10)), loc(10, 1));
+    assert_eq!(map.get_loc_in(BytePos(5), ColUnit::Char), loc(40, 1));
+    assert_eq!(map.get_physical_loc(BytePos(9)), loc(4, 0));
+    assert_eq!(map.get_pos(loc(4, 0)), Some(BytePos(9)));
+    assert_eq!(map.get_pos(map.get_loc(BytePos(4))), None);
+    for offset in 0..map.src().len() as SrcOffset + 1 {
+        let pos = BytePos(offset);
+        assert_eq!(map.get_pos(map.get_physical_loc(pos)), Some(pos));
+    }
+
+    assert_eq!(map.reported_filename(LineIdx(2)), &FileName::from("out.xs"));
+    assert_eq!(map.reported_filename(LineIdx(3)), &FileName::from("tmpl.xs"));
+    assert_eq!(map.reported_filename(LineIdx(4)), &FileName::from("tmpl.xs"));
+
+    // Directives are moved with the file and updated on edits
+    map.set_start_pos(BytePos(50));
+    assert_eq!(map.get_loc(BytePos(59)), loc(10, 0));
+    map.edit(Span::new(BytePos(50), BytePos(52)), "");
+    assert_eq!(map.get_loc(BytePos(52)), loc(40, 0));
+    assert_eq!(map.line_directives().map(|d| d.pos).collect::<Vec<_>>(),
+        [BytePos(53), BytePos(56)]);
+    map.edit(Span::new(BytePos(52), BytePos(55)), "");
+    assert_eq!(map.line_directives().count(), 1);
+}
+
+#[test]
+fn char_spans() {
+    let mut map = FileMap::new("<dummy>", "aä😀\n");
+    map.set_start_pos(BytePos(10));
+    let s = |lo, hi| Span::new(BytePos(lo), BytePos(hi));
+
+    assert_eq!(map.char_span_at(BytePos(10)), Ok(s(10, 11)));
+    assert_eq!(map.char_span_at(BytePos(11)), Ok(s(11, 13)));
+    assert_eq!(map.char_span_at(BytePos(13)), Ok(s(13, 17)));
+    assert_eq!(map.char_span_at(BytePos(17)), Ok(s(17, 18)));
+    assert_eq!(map.char_span_at(BytePos(12)), Err(PosError::NotCharBoundary(BytePos(12))));
+    assert_eq!(map.char_span_at(BytePos(18)),
+        Err(PosError::NotEnoughChars { pos: BytePos(18), n: 1 }));
+    assert_eq!(map.char_span_at(BytePos(9)), Err(PosError::OutOfBounds(BytePos(9))));
+
+    assert_eq!(map.span_of_chars(BytePos(10), 3), Ok(s(10, 17)));
+    assert_eq!(map.span_of_chars(BytePos(11), 3), Ok(s(11, 18)));
+    assert_eq!(map.span_of_chars(BytePos(11), 0), Ok(s(11, 11)));
+    assert_eq!(map.span_of_chars(BytePos(11), 4),
+        Err(PosError::NotEnoughChars { pos: BytePos(11), n: 4 }));
+
+    assert_eq!(map.next_char_boundary(BytePos(10)), Some(BytePos(11)));
+    assert_eq!(map.next_char_boundary(BytePos(11)), Some(BytePos(13)));
+    assert_eq!(map.next_char_boundary(BytePos(14)), Some(BytePos(17)));
+    assert_eq!(map.next_char_boundary(BytePos(17)), Some(BytePos(18)));
+    assert_eq!(map.next_char_boundary(BytePos(18)), None);
+    assert_eq!(map.prev_char_boundary(BytePos(18)), Some(BytePos(17)));
+    assert_eq!(map.prev_char_boundary(BytePos(17)), Some(BytePos(13)));
+    assert_eq!(map.prev_char_boundary(BytePos(16)), Some(BytePos(13)));
+    assert_eq!(map.prev_char_boundary(BytePos(11)), Some(BytePos(10)));
+    assert_eq!(map.prev_char_boundary(BytePos(10)), None);
+    assert_eq!(map.prev_char_boundary(BytePos(30)), None);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_roundtrip() {
+    extern crate serde_json;
+
+    let mut map = FileMap::new("<dummy>", "foo\r\nbar\n");
+    map.set_start_pos(BytePos(7));
+    let json = serde_json::to_string(&map).unwrap();
+    assert!(!json.contains("lines"));
+
+    let back: FileMap = serde_json::from_str(&json).unwrap();
+    assert_eq!(back.filename(), map.filename());
+    assert_eq!(back.src(), map.src());
+    assert_eq!(back.start_pos(), BytePos(7));
+    assert_eq!(back.line_ending(), map.line_ending());
+    assert_eq!(back.num_lines(), 3);
+    assert_eq!(back.get_loc(BytePos(12)), map.get_loc(BytePos(12)));
+
+    let span = Span::new(BytePos(3), BytePos(5));
+    assert_eq!(serde_json::to_string(&span).unwrap(), "[3,5]");
+    let expn = span.with_ctxt(::code::ExpnId(2));
+    assert_eq!(serde_json::to_string(&expn).unwrap(), "[3,5,2]");
+    assert_eq!(serde_json::from_str::<Span>("[3,5,2]").unwrap(), expn);
+    assert_eq!(serde_json::from_str::<Span>("[3,5]").unwrap(), span);
+    assert!(serde_json::from_str::<Span>("[3]").is_err());
+    assert!(serde_json::from_str::<Span>("[3,5,2,1]").is_err());
+}
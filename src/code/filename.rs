@@ -0,0 +1,96 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// The name of the file a `FileMap` was created from. Apart from real files
+/// this can describe several kinds of synthetic files.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub enum FileName {
+    /// A real file with the given path
+    Real(PathBuf),
+    /// A virtual or anonymous file with the given name (e.g. the file used
+    /// in tests), displayed as `<name>`
+    Virtual(String),
+    /// Input read from stdin
+    Stdin,
+    /// Code generated by an expansion of the macro with the given name
+    MacroExpansion(String),
+    /// The n-th input of a REPL session
+    Repl(u32),
+}
+
+impl FileName {
+    /// Checks if this is the name of a real file.
+    pub fn is_real(&self) -> bool {
+        matches!(*self, FileName::Real(_))
+    }
+
+    /// Returns the path if this is the name of a real file.
+    pub fn path(&self) -> Option<&Path> {
+        match *self {
+            FileName::Real(ref path) => Some(path),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for FileName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FileName::Real(ref path) => path.display().fmt(f),
+            FileName::Virtual(ref name) => write!(f, "<{}>", name),
+            FileName::Stdin => "<stdin>".fmt(f),
+            FileName::MacroExpansion(ref name) => {
+                write!(f, "<macro expansion of `{}`>", name)
+            }
+            FileName::Repl(n) => write!(f, "<repl input {}>", n),
+        }
+    }
+}
+
+/// Names of the form `<...>` are virtual files, all others real files.
+impl From<String> for FileName {
+    fn from(name: String) -> FileName {
+        if name.len() >= 2 && name.starts_with('<') && name.ends_with('>') {
+            FileName::Virtual(name[1..name.len() - 1].into())
+        } else {
+            FileName::Real(name.into())
+        }
+    }
+}
+
+/// Names of the form `<...>` are virtual files, all others real files.
+impl<'a> From<&'a str> for FileName {
+    fn from(name: &'a str) -> FileName {
+        FileName::from(name.to_string())
+    }
+}
+
+impl From<PathBuf> for FileName {
+    fn from(path: PathBuf) -> FileName {
+        FileName::Real(path)
+    }
+}
+
+impl<'a> From<&'a Path> for FileName {
+    fn from(path: &'a Path) -> FileName {
+        FileName::Real(path.into())
+    }
+}
+
+// --- tests ---
+#[test]
+fn file_names() {
+    assert_eq!(FileName::from("<dummy>"), FileName::Virtual("dummy".into()));
+    assert_eq!(FileName::from("src/a.xs"), FileName::Real("src/a.xs".into()));
+    assert_eq!(FileName::from("<"), FileName::Real("<".into()));
+
+    assert_eq!(FileName::from("<dummy>").to_string(), "<dummy>");
+    assert_eq!(FileName::from("src/a.xs").to_string(), "src/a.xs");
+    assert_eq!(FileName::Stdin.to_string(), "<stdin>");
+    assert_eq!(FileName::Repl(3).to_string(), "<repl input 3>");
+
+    assert!(FileName::from("a.xs").is_real());
+    assert!(!FileName::Stdin.is_real());
+    assert_eq!(FileName::from("a.xs").path(), Some(Path::new("a.xs")));
+}
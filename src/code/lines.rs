@@ -0,0 +1,137 @@
+use super::{ColIdx, FileMap, LineIdx, Loc};
+use std::cmp::min;
+use std::ops::Range;
+
+/// Iterator over the lines of a `FileMap`, created by `FileMap::lines`.
+/// Yields the index and the content (without line break) of each line.
+#[derive(Clone, Debug)]
+pub struct Lines<'a> {
+    map: &'a FileMap,
+    range: Range<u32>,
+}
+
+impl<'a> Lines<'a> {
+    pub(super) fn new(map: &'a FileMap) -> Lines<'a> {
+        Lines {
+            map,
+            range: 0..map.num_lines() as u32,
+        }
+    }
+
+    fn line(&self, idx: u32) -> (LineIdx, &'a str) {
+        let line = LineIdx(idx);
+        // We can unwrap, because the range only contains valid lines
+        (line, self.map.get_line(line).unwrap())
+    }
+}
+
+impl<'a> Iterator for Lines<'a> {
+    type Item = (LineIdx, &'a str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.range.next().map(|idx| self.line(idx))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.range.size_hint()
+    }
+}
+
+impl<'a> DoubleEndedIterator for Lines<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.range.next_back().map(|idx| self.line(idx))
+    }
+}
+
+impl<'a> ExactSizeIterator for Lines<'a> {}
+
+/// One line covered by a span, as yielded by `SpanLines`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SpanLine<'a> {
+    /// Index of the line
+    pub line: LineIdx,
+    /// Content of the whole line (without line break)
+    pub text: &'a str,
+    /// Byte column at which the covered part of the line starts
+    pub start: ColIdx,
+    /// Byte column at which the covered part of the line ends (exclusive)
+    pub end: ColIdx,
+}
+
+impl<'a> SpanLine<'a> {
+    /// The part of the line before the covered part.
+    pub fn before(&self) -> &'a str {
+        &self.text[..self.start.0 as usize]
+    }
+
+    /// The part of the line covered by the span.
+    pub fn covered(&self) -> &'a str {
+        &self.text[self.start.0 as usize..self.end.0 as usize]
+    }
+
+    /// The part of the line after the covered part.
+    pub fn after(&self) -> &'a str {
+        &self.text[self.end.0 as usize..]
+    }
+}
+
+/// Iterator over the lines a span covers, created by `FileMap::span_lines`.
+#[derive(Clone, Debug)]
+pub struct SpanLines<'a> {
+    map: &'a FileMap,
+    range: Range<u32>,
+    start: Loc,
+    end: Loc,
+}
+
+impl<'a> SpanLines<'a> {
+    pub(super) fn new(map: &'a FileMap, start: Loc, end: Loc) -> SpanLines<'a> {
+        SpanLines {
+            map,
+            range: start.line.0..end.line.0 + 1,
+            start,
+            end,
+        }
+    }
+
+    fn line(&self, idx: u32) -> SpanLine<'a> {
+        let line = LineIdx(idx);
+        // We can unwrap, because the range only contains valid lines
+        let text = self.map.get_line(line).unwrap();
+
+        // Positions of line break chars are moved to the line's end
+        let clamp = |col: ColIdx| ColIdx(min(col.0, text.len() as u32));
+        let start = if line == self.start.line {
+            clamp(self.start.col)
+        } else {
+            ColIdx(0)
+        };
+        let end = if line == self.end.line {
+            clamp(self.end.col)
+        } else {
+            ColIdx(text.len() as u32)
+        };
+
+        SpanLine { line, text, start, end }
+    }
+}
+
+impl<'a> Iterator for SpanLines<'a> {
+    type Item = SpanLine<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.range.next().map(|idx| self.line(idx))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.range.size_hint()
+    }
+}
+
+impl<'a> DoubleEndedIterator for SpanLines<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.range.next_back().map(|idx| self.line(idx))
+    }
+}
+
+impl<'a> ExactSizeIterator for SpanLines<'a> {}
@@ -0,0 +1,342 @@
+use super::{SrcOffset, BytePos, Span, FileMap, FileName, CodeMap};
+use diag::Report;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::fmt;
+
+/// Encodings of source files on disk. Internally, the source is always
+/// stored as UTF-8.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    /// ISO 8859-1: every byte is one char
+    Latin1,
+}
+
+impl Encoding {
+    /// Returns the byte order mark of this encoding (if there is one).
+    pub fn bom(&self) -> Option<&'static [u8]> {
+        match *self {
+            Encoding::Utf8 => Some(b"\xEF\xBB\xBF"),
+            Encoding::Utf16Le => Some(b"\xFF\xFE"),
+            Encoding::Utf16Be => Some(b"\xFE\xFF"),
+            Encoding::Latin1 => None,
+        }
+    }
+}
+
+/// Options for loading files (see `FileMap::load_with`).
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub struct LoadOptions {
+    /// The expected encoding. If it's `Utf8` (the default) and the file
+    /// starts with a UTF-16 byte order mark, the file is read as UTF-16.
+    pub encoding: Encoding,
+}
+
+/// Errors that can occur while loading a file.
+#[derive(Debug)]
+pub enum LoadError {
+    /// The file couldn't be read
+    Io(io::Error),
+    /// The file is not valid in the expected encoding. The `report` points
+    /// to the first offending byte in `file`, which contains the lossily
+    /// decoded source (with invalid parts replaced by U+FFFD), so that the
+    /// report can be printed.
+    InvalidEncoding {
+        report: Report,
+        file: Box<FileMap>,
+    },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LoadError::Io(ref e) => write!(f, "couldn't read file: {}", e),
+            LoadError::InvalidEncoding { ref report, ref file } => {
+                write!(f, "{}: {}", file.filename(), report.remarks[0].desc)
+            }
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            LoadError::Io(ref e) => Some(e),
+            LoadError::InvalidEncoding { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+impl FileMap {
+    /// Reads the file at the given path as UTF-8. See `load_with`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<FileMap, LoadError> {
+        Self::load_with(path, LoadOptions::default())
+    }
+
+    /// Reads the file at the given path and decodes it according to the
+    /// given options. The path is used as filename.
+    pub fn load_with<P: AsRef<Path>>(path: P, opts: LoadOptions)
+        -> Result<FileMap, LoadError>
+    {
+        let path = path.as_ref();
+        let bytes = fs::read(path)?;
+        Self::from_bytes(path, &bytes, opts)
+    }
+
+    /// Decodes the given raw file content according to the given options.
+    /// A byte order mark is stripped and recorded (see `has_bom`). Content
+    /// in other encodings than UTF-8 is transcoded, but positions can still
+    /// be mapped back to the original bytes (see `original_offset`).
+    pub fn from_bytes<S>(filename: S, bytes: &[u8], opts: LoadOptions)
+        -> Result<FileMap, LoadError>
+        where S: Into<FileName>
+    {
+        let filename = filename.into();
+
+        // A UTF-16 BOM overrides the default encoding
+        let encoding = match opts.encoding {
+            Encoding::Utf8 => {
+                [Encoding::Utf16Le, Encoding::Utf16Be].iter()
+                    .cloned()
+                    .find(|e| bytes.starts_with(e.bom().unwrap()))
+                    .unwrap_or(Encoding::Utf8)
+            }
+            e => e,
+        };
+        let (bom, body) = match encoding.bom() {
+            Some(b) if bytes.starts_with(b) => (true, &bytes[b.len()..]),
+            _ => (false, bytes),
+        };
+
+        let Decoded { src, replacements, error } = match encoding {
+            Encoding::Utf8 => decode_utf8(body),
+            Encoding::Utf16Le => decode_utf16(body, u16::from_le_bytes),
+            Encoding::Utf16Be => decode_utf16(body, u16::from_be_bytes),
+            Encoding::Latin1 => Decoded {
+                src: body.iter().map(|&b| b as char).collect(),
+                replacements: Vec::new(),
+                error: None,
+            },
+        };
+
+        let mut file = FileMap::new(filename, src);
+        file.set_encoding(encoding, bom);
+        file.set_replacements(replacements);
+
+        match error {
+            None => Ok(file),
+            Some((pos, msg)) => {
+                let len = '\u{FFFD}'.len_utf8() as SrcOffset;
+                let span = Span::new(pos, pos + BytePos(len));
+                let msg = format!("{} at byte offset {}",
+                    msg,
+                    // We can unwrap, because `pos` lies within the file
+                    file.original_offset(pos).unwrap(),
+                );
+                Err(LoadError::InvalidEncoding {
+                    report: Report::simple_error(msg, span),
+                    file: Box::new(file),
+                })
+            }
+        }
+    }
+}
+
+impl CodeMap {
+    /// Loads the file at the given path (see `FileMap::load`) and adds it to
+    /// this codemap.
+    pub fn load_file<P: AsRef<Path>>(&mut self, path: P)
+        -> Result<Arc<FileMap>, LoadError>
+    {
+        FileMap::load(path).map(|f| self.add_filemap(f))
+    }
+}
+
+/// The result of lossily decoding a file.
+struct Decoded {
+    src: String,
+    /// Position of each replacement char in `src` and the length of the
+    /// invalid bytes it replaced
+    replacements: Vec<(BytePos, SrcOffset)>,
+    /// Position of the first error in `src` together with an error message
+    error: Option<(BytePos, String)>,
+}
+
+/// Decodes UTF-8 lossily (like `String::from_utf8_lossy`).
+fn decode_utf8(bytes: &[u8]) -> Decoded {
+    let mut out = String::with_capacity(bytes.len());
+    let mut replacements = Vec::new();
+    let mut error = None;
+    for chunk in bytes.utf8_chunks() {
+        out.push_str(chunk.valid());
+        let invalid = chunk.invalid();
+        if invalid.is_empty() {
+            continue;
+        }
+
+        let pos = BytePos(out.len() as SrcOffset);
+        if error.is_none() {
+            let msg = format!("invalid UTF-8: unexpected byte 0x{:02X}",
+                invalid[0]);
+            error = Some((pos, msg));
+        }
+        replacements.push((pos, invalid.len() as SrcOffset));
+        out.push('\u{FFFD}');
+    }
+
+    Decoded { src: out, replacements, error }
+}
+
+/// Decodes UTF-16 lossily.
+fn decode_utf16<F>(bytes: &[u8], to_unit: F) -> Decoded
+    where F: Fn([u8; 2]) -> u16
+{
+    let units = bytes.chunks(2).map(|c| {
+        if c.len() == 2 { to_unit([c[0], c[1]]) } else { 0xFFFF }
+    });
+
+    let mut out = String::with_capacity(bytes.len());
+    let mut replacements = Vec::new();
+    let mut error = None;
+    for c in ::std::char::decode_utf16(units) {
+        let c = c.unwrap_or_else(|e| {
+            let pos = BytePos(out.len() as SrcOffset);
+            if error.is_none() {
+                let msg = format!("invalid UTF-16: unpaired surrogate 0x{:04X}",
+                    e.unpaired_surrogate());
+                error = Some((pos, msg));
+            }
+            replacements.push((pos, 2));
+            '\u{FFFD}'
+        });
+        out.push(c);
+    }
+
+    if bytes.len() % 2 == 1 {
+        // The incomplete last unit was decoded as U+FFFF
+        out.pop();
+        let pos = BytePos(out.len() as SrcOffset);
+        if error.is_none() {
+            let msg = "invalid UTF-16: odd number of bytes".to_string();
+            error = Some((pos, msg));
+        }
+        replacements.push((pos, 1));
+        out.push('\u{FFFD}');
+    }
+
+    Decoded { src: out, replacements, error }
+}
+
+// --- tests ---
+#[test]
+fn decoding() {
+    use code::PosError;
+
+    let load = |bytes: &[u8], encoding| {
+        FileMap::from_bytes("<dummy>", bytes, LoadOptions { encoding })
+    };
+
+    let f = load(b"\xEF\xBB\xBFfoo", Encoding::Utf8).unwrap();
+    assert_eq!(f.src(), "foo");
+    assert!(f.has_bom());
+    assert_eq!(f.original_offset(BytePos(1)), Ok(4));
+
+    let f = load(b"\xFF\xFEa\x00\xE4\x00b\x00", Encoding::Utf8).unwrap();
+    assert_eq!(f.encoding(), Encoding::Utf16Le);
+    assert_eq!(f.src(), "aäb");
+    assert_eq!(f.original_offset(BytePos(3)), Ok(6));
+
+    let f = load(b"\x00a\xD8\x3D\xDE\x00", Encoding::Utf16Be).unwrap();
+    assert!(!f.has_bom());
+    assert_eq!(f.src(), "a😀");
+
+    let f = load(b"b\xE4r", Encoding::Latin1).unwrap();
+    assert_eq!(f.src(), "bär");
+    assert_eq!(f.original_offset(BytePos(3)), Ok(2));
+
+    let mut map = CodeMap::new();
+    map.add_file("<a>", "abc");
+    let f = map.add_filemap(load(b"d", Encoding::Latin1).unwrap());
+    assert_eq!(f.original_offset(BytePos(0)), Err(PosError::OutOfBounds(BytePos(0))));
+    assert_eq!(f.original_offset(f.end_pos()), Ok(1));
+}
+
+#[test]
+fn invalid_encoding() {
+    use code::PosError;
+
+    let opts = LoadOptions::default();
+    match FileMap::from_bytes("<dummy>", b"\xEF\xBB\xBFab\xFFc", opts) {
+        Err(LoadError::InvalidEncoding { report, file }) => {
+            assert_eq!(file.src(), "ab\u{FFFD}c");
+            assert_eq!(report.span, Some(Span::new(BytePos(2), BytePos(5))));
+            assert!(report.remarks[0].desc.contains("byte offset 5"));
+        }
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    let opts = LoadOptions { encoding: Encoding::Utf16Le };
+    match FileMap::from_bytes("<dummy>", b"a\x00\x00\xD8b\x00", opts) {
+        Err(LoadError::InvalidEncoding { report, file }) => {
+            assert_eq!(file.src(), "a\u{FFFD}b");
+            assert_eq!(report.span, Some(Span::new(BytePos(1), BytePos(4))));
+            assert!(report.remarks[0].desc.contains("byte offset 2"));
+        }
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    // Positions after replaced bytes are mapped back correctly
+    let opts = LoadOptions::default();
+    match FileMap::from_bytes("<dummy>", b"a\xFFb\xE2\x82c", opts) {
+        Err(LoadError::InvalidEncoding { file, .. }) => {
+            assert_eq!(file.src(), "a\u{FFFD}b\u{FFFD}c");
+            assert_eq!(file.original_offset(BytePos(1)), Ok(1));
+            assert_eq!(file.original_offset(BytePos(4)), Ok(2));
+            assert_eq!(file.original_offset(BytePos(8)), Ok(5));
+            assert_eq!(file.original_offset(BytePos(9)), Ok(6));
+            assert_eq!(file.original_offset(BytePos(10)),
+                Err(PosError::OutOfBounds(BytePos(10))));
+
+            // Edits shift or drop the replaced bytes
+            let mut file = *file;
+            file.edit(Span::new(BytePos(0), BytePos(1)), "xy");
+            assert_eq!(file.original_offset(BytePos(9)), Ok(6));
+            file.edit(Span::new(BytePos(2), BytePos(5)), "z");
+            assert_eq!(file.original_offset(BytePos(7)), Ok(6));
+        }
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    let opts = LoadOptions { encoding: Encoding::Utf16Be };
+    match FileMap::from_bytes("<dummy>", b"\x00a\xDC\x00\x00b\x00", opts) {
+        Err(LoadError::InvalidEncoding { file, .. }) => {
+            assert_eq!(file.src(), "a\u{FFFD}b\u{FFFD}");
+            assert_eq!(file.original_offset(BytePos(4)), Ok(4));
+            assert_eq!(file.original_offset(BytePos(8)), Ok(7));
+        }
+        r => panic!("unexpected result: {:?}", r),
+    }
+}
+
+#[test]
+fn io_error() {
+    match FileMap::load("/this/file/does/not/exist.xs") {
+        Err(LoadError::Io(_)) => {}
+        r => panic!("unexpected result: {:?}", r),
+    }
+}
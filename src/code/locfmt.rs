@@ -0,0 +1,292 @@
+use super::{BytePos, Span, LineIdx, ColIdx, Loc, ColUnit};
+use super::{CodeMap, FileLookup, FileMap, FileName, PosError, LocParseError};
+
+/// Formats and parses locations like `main.xs:12:5` and ranges like
+/// `main.xs:12:5-14:2`. The end of a range is exclusive: it's the location
+/// directly after the last char of the span.
+///
+/// By `default()` lines and columns are 1-based and columns count chars.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub struct LocFormat {
+    /// Whether lines and columns start at 1 instead of 0
+    pub one_based: bool,
+    /// The unit columns are measured in
+    pub unit: ColUnit,
+}
+
+impl Default for LocFormat {
+    fn default() -> Self {
+        LocFormat {
+            one_based: true,
+            unit: ColUnit::Char,
+        }
+    }
+}
+
+/// A parsed, but not yet resolved location string (see `LocFormat::parse`).
+/// Lines and columns are 0-based; columns are measured in the unit of the
+/// `LocFormat`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LocRef {
+    pub filename: FileName,
+    pub lo: Loc,
+    /// End of the range (exclusive), if a range was given
+    pub hi: Option<Loc>,
+}
+
+impl LocFormat {
+    /// Returns the default format (see `LocFormat`).
+    pub fn new() -> LocFormat {
+        LocFormat::default()
+    }
+
+    /// Lines and columns start at 0.
+    pub fn zero_based(self) -> LocFormat {
+        LocFormat { one_based: false, ..self }
+    }
+
+    /// Columns are measured in the given unit.
+    pub fn with_unit(self, unit: ColUnit) -> LocFormat {
+        LocFormat { unit, ..self }
+    }
+
+    /// Formats the location as `line:col`. The column of `loc` has to be
+    /// measured in the unit of this format already.
+    pub fn loc(&self, loc: Loc) -> String {
+        let base = self.base();
+        format!("{}:{}", loc.line.0 + base, loc.col.0 + base)
+    }
+
+    /// Formats the position as `file:line:col`. The file name and line
+    /// honour line directives (see `FileMap::add_line_directive`). Such a
+    /// location can't be parsed back by `parse_pos`, which expects physical
+    /// lines of a file in the `CodeMap`.
+    pub fn pos<M>(&self, map: &M, pos: BytePos) -> Result<String, PosError>
+        where M: FileLookup + ?Sized
+    {
+        let file = map.lookup_filemap(pos).ok_or(PosError::OutOfBounds(pos))?;
+        file.check_pos(pos)?;
+        Ok(format!("{}:{}", self.filename(file, pos), self.loc_of(file, pos)))
+    }
+
+    /// Formats the span as `file:line:col-line:col`. The file name and
+    /// lines honour line directives, like in `pos`.
+    pub fn span<M>(&self, map: &M, span: Span) -> Result<String, PosError>
+        where M: FileLookup + ?Sized
+    {
+        let file = map.span_filemap(span).ok_or(if span.is_dummy() {
+            PosError::DummySpan
+        } else {
+            PosError::OutOfBounds(span.lo)
+        })?;
+        file.check_span(span)?;
+        Ok(format!("{}:{}-{}",
+            self.filename(file, span.lo),
+            self.loc_of(file, span.lo),
+            self.loc_of(file, span.hi),
+        ))
+    }
+
+    /// Parses a string of the form `file:line[:col][-line:col]`. The column
+    /// defaults to the start of the line.
+    pub fn parse(&self, s: &str) -> Result<LocRef, LocParseError> {
+        // A range is recognized by its end `-line:col`. Since file names
+        // may contain dashes as well, the string is parsed as a single
+        // location if that fails.
+        let range = s.rfind('-').and_then(|dash| {
+            let (line, col) = split_num(&s[dash + 1..])?;
+            let hi = (parse_num(line)?, col);
+            let (file, lo) = split_line_col(&s[..dash], true)?;
+            Some((file, lo, Some(hi)))
+        });
+        let single = || {
+            split_line_col(s, false).map(|(file, lo)| (file, lo, None))
+        };
+        let (file, lo, hi) = range.or_else(single)
+            .filter(|&(file, _, _)| !file.is_empty())
+            .ok_or_else(|| LocParseError::InvalidSyntax(s.to_owned()))?;
+
+        let lo = match lo {
+            (line, Some(col)) => (line, col),
+            (line, None) => (line, self.base()),
+        };
+        Ok(LocRef {
+            filename: file.into(),
+            lo: self.make_loc(lo)?,
+            hi: hi.map(|hi| self.make_loc(hi)).transpose()?,
+        })
+    }
+
+    /// Parses a location string (see `parse`) and resolves it to a position
+    /// in one of the files of `map`. Lines are physical lines, i.e. line
+    /// directives are ignored. Hence, only locations formatted by `pos` for
+    /// files without line directives can be parsed back.
+    pub fn parse_pos(&self, map: &CodeMap, s: &str)
+        -> Result<BytePos, LocParseError>
+    {
+        let loc_ref = self.parse(s)?;
+        if loc_ref.hi.is_some() {
+            return Err(LocParseError::UnexpectedRange);
+        }
+
+        let file = self.find_file(map, &loc_ref.filename)?;
+        Ok(self.resolve(file, loc_ref.lo)?)
+    }
+
+    /// Like `parse_pos`, but a range is accepted as well. A single location
+    /// results in an empty span.
+    pub fn parse_span(&self, map: &CodeMap, s: &str)
+        -> Result<Span, LocParseError>
+    {
+        let loc_ref = self.parse(s)?;
+        let file = self.find_file(map, &loc_ref.filename)?;
+        let lo = self.resolve(file, loc_ref.lo)?;
+        let hi = match loc_ref.hi {
+            Some(hi) => self.resolve(file, hi)?,
+            None => lo,
+        };
+
+        if hi < lo {
+            // We can unwrap, because there is an end if it differs from `lo`
+            let hi = loc_ref.hi.unwrap();
+            return Err(PosError::ReversedRange { lo: loc_ref.lo, hi }.into());
+        }
+        Ok(Span::new(lo, hi))
+    }
+
+    fn base(&self) -> u32 {
+        if self.one_based { 1 } else { 0 }
+    }
+
+    fn make_loc(&self, (line, col): (u32, u32)) -> Result<Loc, LocParseError> {
+        let base = self.base();
+        if line < base || col < base {
+            return Err(LocParseError::ZeroIndex);
+        }
+        Ok(Loc { line: LineIdx(line - base), col: ColIdx(col - base) })
+    }
+
+    fn filename<'a>(&self, file: &'a FileMap, pos: BytePos) -> &'a FileName {
+        file.reported_filename(file.get_line_idx(pos))
+    }
+
+    fn loc_of(&self, file: &FileMap, pos: BytePos) -> String {
+        self.loc(file.get_loc_in(pos, self.unit))
+    }
+
+    fn find_file<'a>(&self, map: &'a CodeMap, name: &FileName)
+        -> Result<&'a FileMap, LocParseError>
+    {
+        map.file_by_name(name)
+            .map(|f| &**f)
+            .ok_or_else(|| LocParseError::UnknownFile(name.clone()))
+    }
+
+    fn resolve(&self, file: &FileMap, loc: Loc) -> Result<BytePos, PosError> {
+        file.try_get_pos_in(loc, self.unit)
+    }
+}
+
+/// Splits `"foo:12:5"` into `("foo", (12, Some(5)))` and -- if the column
+/// is optional -- `"foo:12"` into `("foo", (12, None))`.
+fn split_line_col(s: &str, need_col: bool)
+    -> Option<(&str, (u32, Option<u32>))>
+{
+    let (head, last) = split_num(s)?;
+    match split_num(head) {
+        Some((file, line)) => Some((file, (line, Some(last)))),
+        None if !need_col => Some((head, (last, None))),
+        None => None,
+    }
+}
+
+/// Splits `"foo:12"` into `("foo", 12)`. Returns `None` if the string doesn't
+/// end with a colon followed by a decimal number.
+fn split_num(s: &str) -> Option<(&str, u32)> {
+    let colon = s.rfind(':')?;
+    parse_num(&s[colon + 1..]).map(|n| (&s[..colon], n))
+}
+
+/// Parses a decimal number without sign.
+fn parse_num(s: &str) -> Option<u32> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+// --- tests ---
+#[test]
+fn formatting() {
+    let mut map = CodeMap::new();
+    map.add_file("a.xs", "foo");
+    let b = map.add_file("src/b.xs", "fn\n  äb()\n");
+    let pos = |p| b.start_pos() + BytePos(p);
+
+    let fmt = LocFormat::new();
+    assert_eq!(fmt.pos(&map, pos(0)).unwrap(), "src/b.xs:1:1");
+    assert_eq!(fmt.pos(&*b, pos(7)).unwrap(), "src/b.xs:2:4");
+    assert_eq!(fmt.with_unit(ColUnit::Byte).pos(&map, pos(7)).unwrap(), "src/b.xs:2:5");
+    assert_eq!(fmt.zero_based().pos(&map, pos(7)).unwrap(), "src/b.xs:1:3");
+    assert_eq!(fmt.span(&map, Span::new(pos(1), pos(7))).unwrap(), "src/b.xs:1:2-2:4");
+    assert_eq!(fmt.loc(Loc { line: LineIdx(0), col: ColIdx(4) }), "1:5");
+
+    assert_eq!(fmt.pos(&map, pos(6)), Err(PosError::NotCharBoundary(pos(6))));
+    assert_eq!(fmt.pos(&map, BytePos(100)), Err(PosError::OutOfBounds(BytePos(100))));
+    assert_eq!(fmt.span(&map, Span::dummy()), Err(PosError::DummySpan));
+
+    assert_eq!(Loc { line: LineIdx(11), col: ColIdx(4) }.to_string(), "12:5");
+}
+
+#[test]
+fn parsing() {
+    let loc = |line, col| Loc { line: LineIdx(line), col: ColIdx(col) };
+    let fmt = LocFormat::new();
+
+    let r = fmt.parse("main.xs:12:5").unwrap();
+    assert_eq!(r, LocRef { filename: "main.xs".into(), lo: loc(11, 4), hi: None });
+    let r = fmt.parse("main.xs:12").unwrap();
+    assert_eq!(r, LocRef { filename: "main.xs".into(), lo: loc(11, 0), hi: None });
+    let r = fmt.parse("C:\\my-dir\\main.xs:12:5-14:2").unwrap();
+    assert_eq!(r.filename, FileName::from("C:\\my-dir\\main.xs"));
+    assert_eq!((r.lo, r.hi), (loc(11, 4), Some(loc(13, 1))));
+    let r = fmt.zero_based().parse("main.xs:0:0").unwrap();
+    assert_eq!(r.lo, loc(0, 0));
+
+    let invalid = |s: &str| Err(LocParseError::InvalidSyntax(s.into()));
+    assert_eq!(fmt.parse("main.xs"), invalid("main.xs"));
+    assert_eq!(fmt.parse(":12:5"), invalid(":12:5"));
+    assert_eq!(fmt.parse("main.xs:12:"), invalid("main.xs:12:"));
+    assert_eq!(fmt.parse("main.xs:12:5-14"), invalid("main.xs:12:5-14"));
+    assert_eq!(fmt.parse("main.xs:0:5"), Err(LocParseError::ZeroIndex));
+
+    let mut map = CodeMap::new();
+    map.add_file("a.xs", "foo");
+    let b = map.add_file("b.xs", "fn\n  äb()\n");
+    let pos = |p| b.start_pos() + BytePos(p);
+
+    assert_eq!(fmt.parse_pos(&map, "b.xs:2:4"), Ok(pos(7)));
+    assert_eq!(fmt.parse_pos(&map, "b.xs:2"), Ok(pos(3)));
+    assert_eq!(fmt.parse_span(&map, "b.xs:1:2-2:4"), Ok(Span::new(pos(1), pos(7))));
+    assert_eq!(fmt.parse_span(&map, "b.xs:2:4"), Ok(Span::new(pos(7), pos(7))));
+    assert_eq!(fmt.parse_pos(&map, "b.xs:1:1-1:2"), Err(LocParseError::UnexpectedRange));
+    assert_eq!(fmt.parse_pos(&map, "c.xs:1:1"),
+        Err(LocParseError::UnknownFile("c.xs".into())));
+    assert_eq!(fmt.parse_pos(&map, "b.xs:9:1"),
+        Err(LocParseError::Pos(PosError::LineOutOfBounds(LineIdx(8)))));
+    assert_eq!(fmt.parse_span(&map, "b.xs:2:1-1:1"),
+        Err(LocParseError::Pos(PosError::ReversedRange { lo: loc(1, 0), hi: loc(0, 0) })));
+
+    let visual = fmt.with_unit(ColUnit::Visual { tab_width: 4 });
+    let c = map.add_file("c.xs", "日本\n");
+    assert_eq!(visual.parse_pos(&map, "c.xs:1:3"), Ok(c.start_pos() + BytePos(3)));
+    assert_eq!(visual.parse_pos(&map, "c.xs:1:2"),
+        Err(LocParseError::Pos(PosError::ColNotOnCharBoundary(loc(0, 1)))));
+    assert_eq!(visual.parse_pos(&map, "c.xs:1:6"),
+        Err(LocParseError::Pos(PosError::ColPastEndOfLine(loc(0, 5)))));
+
+    for s in &["b.xs:1:1", "b.xs:2:4", "b.xs:3:1"] {
+        assert_eq!(fmt.pos(&map, fmt.parse_pos(&map, s).unwrap()).unwrap(), *s);
+    }
+}
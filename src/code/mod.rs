@@ -1,11 +1,41 @@
 //! This module contains types and functions to work on the raw source code.
 //!
-//! Currently we do not distinguish between code-map and file-map, since one
-//! file always contains the whole code so far. However, this might change!
+//! A `FileMap` stores the content of one file. Multiple files are managed by
+//! a `CodeMap` which assigns each file a disjoint range of positions, such
+//! that every `BytePos` and `Span` can be resolved back to its file.
 //!
 
 mod pos;
 mod filemap;
+mod codemap;
+mod error;
+mod edit;
+mod load;
+mod expn;
+mod spanned;
+mod filename;
+mod compact;
+mod lines;
+mod origin;
+mod directive;
+mod locfmt;
+mod cursor;
+mod search;
+pub mod width;
 
-pub use self::pos::{SrcOffset, BytePos, Span, LineIdx, ColIdx, Loc};
-pub use self::filemap::FileMap;
+pub use self::pos::{SrcOffset, BytePos, Span, LineIdx, ColIdx, Loc, ColUnit};
+pub use self::filemap::{FileMap, LineEnding};
+pub use self::codemap::{CodeMap, FileLookup};
+pub use self::error::{PosError, LocParseError};
+pub use self::edit::Edit;
+pub use self::load::{Encoding, LoadOptions, LoadError};
+pub use self::expn::{ExpnId, ExpnInfo};
+pub use self::spanned::{HasSpan, Spanned};
+pub use self::filename::FileName;
+pub use self::compact::CompactSpan;
+pub use self::lines::{Lines, SpanLine, SpanLines};
+pub use self::origin::Origin;
+pub use self::directive::LineDirective;
+pub use self::locfmt::{LocFormat, LocRef};
+pub use self::cursor::Cursor;
+pub use self::search::is_ident_char;
@@ -0,0 +1,73 @@
+use super::{BytePos, Span};
+
+/// Maps a range of generated code (e.g. the output of a preprocessor) back
+/// to the code it was created from. Origins are attached to the `FileMap` of
+/// the generated code via `FileMap::add_origin`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub struct Origin {
+    /// Span of the generated code
+    pub generated: Span,
+    /// Span of the original code, usually in another file of the `CodeMap`
+    pub original: Span,
+    /// Whether the generated code is a verbatim copy of the original code.
+    /// If so, positions are mapped byte by byte. Otherwise every span within
+    /// `generated` maps to the whole `original` span.
+    pub verbatim: bool,
+}
+
+impl Origin {
+    /// Creates the origin of code which was copied verbatim from the
+    /// original code starting at `original` (e.g. an included file).
+    pub fn copied(generated: Span, original: BytePos) -> Origin {
+        Origin {
+            generated,
+            original: Span::new(original, original + BytePos(generated.len())),
+            verbatim: true,
+        }
+    }
+
+    /// Creates the origin of code which was generated from the `original`
+    /// code (e.g. by substituting a template).
+    pub fn generated(generated: Span, original: Span) -> Origin {
+        Origin {
+            generated,
+            original,
+            verbatim: false,
+        }
+    }
+
+    /// Maps a span within the generated code to the original code. Returns
+    /// `None` if the span doesn't lie within `generated`.
+    pub fn map_span(&self, span: Span) -> Option<Span> {
+        if !self.generated.contains(span) {
+            return None;
+        }
+
+        if self.verbatim {
+            let lo = self.original.lo + (span.lo - self.generated.lo);
+            let hi = self.original.lo + (span.hi - self.generated.lo);
+            Some(Span::new(lo, hi).with_ctxt(self.original.ctxt))
+        } else {
+            Some(self.original)
+        }
+    }
+}
+
+// --- tests ---
+#[test]
+fn span_mapping() {
+    let s = |lo, hi| Span::new(BytePos(lo), BytePos(hi));
+
+    let copied = Origin::copied(s(10, 20), BytePos(100));
+    assert_eq!(copied.original, s(100, 110));
+    assert_eq!(copied.map_span(s(12, 15)), Some(s(102, 105)));
+    assert_eq!(copied.map_span(s(10, 20)), Some(s(100, 110)));
+    assert_eq!(copied.map_span(s(20, 20)), Some(s(110, 110)));
+    assert_eq!(copied.map_span(s(5, 15)), None);
+    assert_eq!(copied.map_span(Span::dummy()), None);
+
+    let generated = Origin::generated(s(10, 20), s(3, 7));
+    assert_eq!(generated.map_span(s(12, 15)), Some(s(3, 7)));
+    assert_eq!(generated.map_span(s(15, 25)), None);
+}
@@ -4,6 +4,7 @@
 use std::ops::{self, Add, Sub};
 use std::cmp::{min, max};
 use std::fmt;
+use super::ExpnId;
 
 // Helps implementing basic operators, like `Add` and `Sub`
 macro_rules! impl_math {
@@ -25,8 +26,9 @@ pub type SrcOffset = u32;
 
 /// Position within source specified by byte offset. This is not equal to
 /// `CharPos` thanks to UTF-8 and multibyte chars. This type always represents
-/// positions relative to the whole codemap.
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Default)]
+/// positions relative to the whole codemap (see `CodeMap`).
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
 pub struct BytePos(pub SrcOffset);
 
 impl_math!(BytePos, Add, add);
@@ -35,40 +37,54 @@ impl_math!(BytePos, Sub, sub);
 
 // ----------------------------------------------------------------------------
 /// A region within the source specified by first and last byte offset. `lo`
-/// byte is included in the span, `hi` byte is excluded.
-#[derive(Clone, Copy, PartialEq, Eq)]
+/// byte is included in the span, `hi` byte is excluded. Additionally, the
+/// span knows the macro expansion it stems from (if any).
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Span {
     /// Low byte, inclusive
     pub lo: BytePos,
     /// High byte, exclusive
     pub hi: BytePos,
+    /// The macro expansion which created this span
+    pub ctxt: ExpnId,
 }
 
 impl Span {
-    /// Creates a span that points to a single char
+    /// Creates a span that points to a single byte. For non-ASCII chars use
+    /// `FileMap::char_span_at` instead.
     pub fn single(pos: BytePos) -> Span {
-        Span { lo: pos, hi: pos + BytePos(1) }
+        Span::new(pos, pos + BytePos(1))
     }
 
     /// Crates an empty span (points between to chars)
     pub fn empty_at(pos: BytePos) -> Span {
-        Span { lo: pos, hi: pos }
+        Span::new(pos, pos)
     }
 
     /// Creates a span from a lo and hi (shorter than struct constructor
-    /// syntax)
+    /// syntax). The span is not part of any macro expansion.
     pub fn new(lo: BytePos, hi: BytePos) -> Span {
-        Span { lo: lo, hi: hi }
+        Span { lo, hi, ctxt: ExpnId::root() }
     }
 
     /// Creates a span from a tuple
     pub fn from_pair((lo, hi): (BytePos, BytePos)) -> Span {
-        Span { lo: lo, hi: hi }
+        Span::new(lo, hi)
     }
 
     /// Creates a dummy span. Should be used with caution.
     pub fn dummy() -> Span {
-        Span { lo: BytePos(1), hi: BytePos(0) }
+        Span::new(BytePos(1), BytePos(0))
+    }
+
+    /// Returns the same span, but with the given expansion context.
+    pub fn with_ctxt(self, ctxt: ExpnId) -> Span {
+        Span { ctxt, ..self }
+    }
+
+    /// Checks if this span was created by a macro expansion.
+    pub fn from_expansion(&self) -> bool {
+        !self.ctxt.is_root()
     }
 
     /// Checks if the this span is a dummy span
@@ -81,20 +97,23 @@ impl Span {
         self.lo == self.hi
     }
 
+    /// Checks if the span ends before it starts. Dummy spans are not
+    /// considered reversed.
+    pub fn is_reversed(&self) -> bool {
+        !self.is_dummy() && self.hi < self.lo
+    }
+
     /// Returns the length (number of bytes) of the span or 0 if it's a dummy
-    /// span
+    /// or reversed span
     pub fn len(&self) -> SrcOffset {
-        if self.is_dummy() {
-            0
-        } else {
-            (self.hi - self.lo).0
-        }
+        self.hi.0.saturating_sub(self.lo.0)
     }
 
     /// Returns the smallest span which encloses both given spans
     ///
     /// If one of given spans is a dummy span, it is ignored and the other span
     /// is returned. If both spans are dummy spans, a dummy span is returned.
+    /// Otherwise the expansion context of `self` is kept.
     pub fn hull(&self, other: &Self) -> Span {
         if self.is_dummy() {
             *other
@@ -104,6 +123,7 @@ impl Span {
             Span {
                 lo: min(self.lo, other.lo),
                 hi: max(self.hi, other.hi),
+                ctxt: self.ctxt,
             }
         }
     }
@@ -117,6 +137,106 @@ impl Span {
             && self.hi >= other.hi
     }
 
+    /// Checks if both spans share at least one byte. Dummy spans never
+    /// overlap with anything.
+    pub fn overlaps(&self, other: Self) -> bool {
+        !self.is_dummy()
+            && !other.is_dummy()
+            && max(self.lo, other.lo) < min(self.hi, other.hi)
+    }
+
+    /// Returns the span covered by both spans. If the spans only touch, the
+    /// result is an empty span. Returns `None` if the spans are disjoint or
+    /// if one of them is a dummy span.
+    pub fn intersect(&self, other: Self) -> Option<Span> {
+        let lo = max(self.lo, other.lo);
+        let hi = min(self.hi, other.hi);
+        if self.is_dummy() || other.is_dummy() || lo > hi {
+            None
+        } else {
+            Some(Span { lo, hi, ctxt: self.ctxt })
+        }
+    }
+
+    /// Returns the parts of this span which are not covered by `other`: the
+    /// part before and the part after `other`. Parts which would be empty
+    /// are `None`. If the spans don't overlap, this span is the part before
+    /// `other` or the part after it, depending on their order. A dummy span
+    /// as `other` covers nothing, while nothing is left of a dummy span.
+    pub fn difference(&self, other: Self) -> (Option<Span>, Option<Span>) {
+        if self.is_dummy() {
+            return (None, None);
+        }
+        if other.is_dummy() || !self.overlaps(other) {
+            let this = Some(*self).filter(|s| !s.is_empty());
+            return if !other.is_dummy() && other.hi <= self.lo {
+                (None, this)
+            } else {
+                (this, None)
+            };
+        }
+
+        let before = self.with_hi(other.lo);
+        let after = self.with_lo(other.hi);
+        (
+            Some(before).filter(|s| s.lo < s.hi),
+            Some(after).filter(|s| s.lo < s.hi),
+        )
+    }
+
+    /// Splits the span into the part before and the part after `pos`.
+    /// Returns `None` if `pos` is not within the span or if it's a dummy
+    /// span.
+    pub fn split_at(&self, pos: BytePos) -> Option<(Span, Span)> {
+        if self.is_dummy() || pos < self.lo || pos > self.hi {
+            None
+        } else {
+            Some((self.with_hi(pos), self.with_lo(pos)))
+        }
+    }
+
+    /// Returns the empty span at the start of this span.
+    pub fn shrink_to_lo(&self) -> Span {
+        self.with_hi(self.lo)
+    }
+
+    /// Returns the empty span at the end of this span.
+    pub fn shrink_to_hi(&self) -> Span {
+        self.with_lo(self.hi)
+    }
+
+    /// Returns the same span, but with a different start. A dummy span stays
+    /// a dummy span.
+    pub fn with_lo(&self, lo: BytePos) -> Span {
+        if self.is_dummy() {
+            *self
+        } else {
+            Span { lo, ..*self }
+        }
+    }
+
+    /// Returns the same span, but with a different end. A dummy span stays a
+    /// dummy span.
+    pub fn with_hi(&self, hi: BytePos) -> Span {
+        if self.is_dummy() {
+            *self
+        } else {
+            Span { hi, ..*self }
+        }
+    }
+
+    /// Returns the span between the end of this span and the start of
+    /// `other`. If one of the spans is a dummy span or if `other` starts
+    /// before this span ends (i.e. there is nothing between them), a dummy
+    /// span is returned.
+    pub fn between(&self, other: Self) -> Span {
+        if self.is_dummy() || other.is_dummy() || other.lo < self.hi {
+            Span::dummy()
+        } else {
+            Span { lo: self.hi, hi: other.lo, ctxt: self.ctxt }
+        }
+    }
+
     /// Returns a range for indexing strings and vectors
     pub fn into_range(self) -> ops::Range<usize> {
         ops::Range {
@@ -129,14 +249,75 @@ impl Span {
 // custom `Debug` impl to shorten debug output and improve readability
 impl fmt::Debug for Span {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "@({}, {})", self.lo.0, self.hi.0)
+        write!(f, "@({}, {})", self.lo.0, self.hi.0)?;
+        if self.from_expansion() {
+            write!(f, "#{}", self.ctxt.0)?;
+        }
+        Ok(())
+    }
+}
+
+
+// Spans are serialized compactly as sequence `[lo, hi]` or -- if they stem
+// from a macro expansion -- `[lo, hi, ctxt]`.
+#[cfg(feature = "serde")]
+impl ::serde::Serialize for Span {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: ::serde::Serializer
+    {
+        use serde::ser::SerializeSeq;
+
+        let len = if self.from_expansion() { 3 } else { 2 };
+        let mut seq = serializer.serialize_seq(Some(len))?;
+        seq.serialize_element(&self.lo.0)?;
+        seq.serialize_element(&self.hi.0)?;
+        if self.from_expansion() {
+            seq.serialize_element(&self.ctxt.0)?;
+        }
+        seq.end()
     }
 }
 
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for Span {
+    fn deserialize<D>(deserializer: D) -> Result<Span, D::Error>
+        where D: ::serde::Deserializer<'de>
+    {
+        use serde::de::{self, SeqAccess, Visitor};
+
+        struct SpanVisitor;
+
+        impl<'de> Visitor<'de> for SpanVisitor {
+            type Value = Span;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a sequence `[lo, hi]` or `[lo, hi, ctxt]`")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Span, A::Error>
+                where A: SeqAccess<'de>
+            {
+                let lo = seq.next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                let hi = seq.next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                let ctxt = seq.next_element()?.unwrap_or(0);
+                if seq.next_element::<de::IgnoredAny>()?.is_some() {
+                    return Err(de::Error::invalid_length(4, &self));
+                }
+
+                Ok(Span::new(BytePos(lo), BytePos(hi)).with_ctxt(ExpnId(ctxt)))
+            }
+        }
+
+        deserializer.deserialize_seq(SpanVisitor)
+    }
+}
 
 // ----------------------------------------------------------------------------
 /// Represents a line index.
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
 pub struct LineIdx(pub SrcOffset);
 
 impl fmt::Display for LineIdx {
@@ -147,8 +328,15 @@ impl fmt::Display for LineIdx {
 
 /// Represents a column index.
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
 pub struct ColIdx(pub SrcOffset);
 
+impl fmt::Display for ColIdx {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (self.0 + 1).fmt(f)
+    }
+}
+
 impl_math!(LineIdx, Add, add);
 impl_math!(LineIdx, Sub, sub);
 impl_math!(ColIdx, Add, add);
@@ -157,18 +345,47 @@ impl_math!(ColIdx, Sub, sub);
 
 /// Location within one file specified by line and column.
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
 pub struct Loc {
     pub line: LineIdx,
     pub col: ColIdx,
 }
 
+/// Formats the location as `line:col` (both 1-based). See `LocFormat` for
+/// other formats.
+impl fmt::Display for Loc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
+/// The unit a column index is measured in. Unless stated otherwise, columns
+/// are byte offsets within the line.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub enum ColUnit {
+    /// Bytes of the UTF-8 encoded line
+    Byte,
+    /// Unicode scalar values (`char`s), sometimes called UTF-32 code units
+    Char,
+    /// UTF-16 code units, as used by many editors and the Language Server
+    /// Protocol
+    Utf16,
+    /// Columns as displayed in a terminal or editor: wide chars occupy two
+    /// columns, combining marks none and tabs advance to the next multiple
+    /// of `tab_width`
+    Visual {
+        tab_width: SrcOffset,
+    },
+}
+
 // --- tests ---
 #[test]
 fn basic_spans() {
     use super::Span;
 
     let s = Span::new(BytePos(3), BytePos(10));
-    assert_eq!(s, Span { lo: BytePos(3), hi: BytePos(10) });
+    assert_eq!(s, Span { lo: BytePos(3), hi: BytePos(10), ctxt: ExpnId(0) });
     assert_eq!(s, Span::from_pair((BytePos(3), BytePos(10))));
     assert_eq!(s.len(), 7);
     assert!(!s.is_dummy());
@@ -183,6 +400,11 @@ fn basic_spans() {
     assert!(!s.contains(Span::dummy()));
 
     assert_eq!(Span::single(BytePos(15)), Span::new(BytePos(15), BytePos(16)));
+
+    let r = Span::new(BytePos(10), BytePos(3));
+    assert!(r.is_reversed());
+    assert_eq!(r.len(), 0);
+    assert!(!Span::dummy().is_reversed());
 }
 
 #[test]
@@ -216,3 +438,61 @@ fn span_hulls() {
     assert_eq!(a.hull(&c), Span::new(BytePos(1), BytePos(9)));
     assert_eq!(b.hull(&c), Span::new(BytePos(3), BytePos(9)));
 }
+
+#[test]
+fn span_contexts() {
+    use super::Span;
+
+    let a = Span::new(BytePos(1), BytePos(5));
+    let b = a.with_ctxt(ExpnId(3));
+    assert!(!a.from_expansion());
+    assert!(b.from_expansion());
+    assert!(a != b);
+    assert_eq!(b.hull(&Span::new(BytePos(4), BytePos(9))).ctxt, ExpnId(3));
+    assert_eq!(format!("{:?}", a), "@(1, 5)");
+    assert_eq!(format!("{:?}", b), "@(1, 5)#3");
+}
+
+#[test]
+fn span_algebra() {
+    use super::Span;
+
+    let s = |lo, hi| Span::new(BytePos(lo), BytePos(hi));
+    let d = Span::dummy();
+
+    assert!(s(1, 5).overlaps(s(4, 8)));
+    assert!(!s(1, 5).overlaps(s(5, 8)));
+    assert!(!s(1, 5).overlaps(d));
+
+    assert_eq!(s(1, 5).intersect(s(3, 8)), Some(s(3, 5)));
+    assert_eq!(s(1, 5).intersect(s(5, 8)), Some(s(5, 5)));
+    assert_eq!(s(1, 5).intersect(s(6, 8)), None);
+    assert_eq!(s(1, 5).intersect(d), None);
+
+    assert_eq!(s(1, 9).difference(s(3, 5)), (Some(s(1, 3)), Some(s(5, 9))));
+    assert_eq!(s(1, 9).difference(s(0, 5)), (None, Some(s(5, 9))));
+    assert_eq!(s(1, 9).difference(s(1, 9)), (None, None));
+    assert_eq!(s(1, 9).difference(s(10, 12)), (Some(s(1, 9)), None));
+    assert_eq!(s(5, 9).difference(s(1, 3)), (None, Some(s(5, 9))));
+    assert_eq!(s(5, 9).difference(s(1, 5)), (None, Some(s(5, 9))));
+    assert_eq!(s(1, 9).difference(d), (Some(s(1, 9)), None));
+    assert_eq!(d.difference(s(1, 9)), (None, None));
+
+    assert_eq!(s(1, 9).split_at(BytePos(4)), Some((s(1, 4), s(4, 9))));
+    assert_eq!(s(1, 9).split_at(BytePos(9)), Some((s(1, 9), s(9, 9))));
+    assert_eq!(s(1, 9).split_at(BytePos(10)), None);
+    assert_eq!(d.split_at(BytePos(0)), None);
+
+    assert_eq!(s(1, 9).shrink_to_lo(), s(1, 1));
+    assert_eq!(s(1, 9).shrink_to_hi(), s(9, 9));
+    assert_eq!(s(1, 9).with_lo(BytePos(3)), s(3, 9));
+    assert_eq!(s(1, 9).with_hi(BytePos(3)), s(1, 3));
+    assert!(d.shrink_to_hi().is_dummy());
+    assert!(d.with_lo(BytePos(5)).is_dummy());
+
+    assert_eq!(s(1, 3).between(s(7, 9)), s(3, 7));
+    assert_eq!(s(1, 3).between(s(3, 9)), s(3, 3));
+    assert!(s(1, 9).between(s(3, 5)).is_dummy());
+    assert!(s(7, 9).between(s(1, 3)).is_dummy());
+    assert!(s(1, 3).between(d).is_dummy());
+}
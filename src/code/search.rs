@@ -0,0 +1,131 @@
+use super::{SrcOffset, BytePos, Span, LineIdx, FileMap};
+
+/// Searching the source for spans. All searches are plain text searches;
+/// word searches additionally require the match not to be part of a longer
+/// identifier (see `is_ident_char`).
+impl FileMap {
+    /// Returns the span of the first occurrence of `pattern` or `None` if
+    /// there is none.
+    pub fn find_span(&self, pattern: &str) -> Option<Span> {
+        self.src()
+            .match_indices(pattern)
+            .next()
+            .map(|(i, m)| self.local_span(i, m.len()))
+    }
+
+    /// Returns the spans of all non-overlapping occurrences of `pattern`.
+    pub fn find_all_spans(&self, pattern: &str) -> Vec<Span> {
+        self.src()
+            .match_indices(pattern)
+            .map(|(i, m)| self.local_span(i, m.len()))
+            .collect()
+    }
+
+    /// Like `find_span`, but only matches which are not directly preceded or
+    /// followed by an identifier char. Searching for `"x"` thus doesn't find
+    /// the `x` in `max`.
+    pub fn find_word(&self, word: &str) -> Option<Span> {
+        self.words(word).next()
+    }
+
+    /// Like `find_all_spans`, but only returns whole words (see
+    /// `find_word`).
+    pub fn find_all_words(&self, word: &str) -> Vec<Span> {
+        self.words(word).collect()
+    }
+
+    /// Returns the span of the `n`-th (0-based) occurrence of `pattern`
+    /// within the given line (without line break) or `None` if there are
+    /// not enough occurrences or if the line doesn't exist.
+    pub fn find_span_on_line(&self, line: LineIdx, pattern: &str, n: usize)
+        -> Option<Span>
+    {
+        let start = self.get_line_start(line)?;
+        self.get_line(line)?
+            .match_indices(pattern)
+            .nth(n)
+            .map(|(i, m)| {
+                let lo = start + BytePos(i as SrcOffset);
+                Span::new(lo, lo + BytePos(m.len() as SrcOffset))
+            })
+    }
+
+    /// Returns the span of the identifier containing `pos` or `None` if
+    /// there is no identifier char at or directly before `pos`. Positions
+    /// outside of this file or not on a char boundary yield `None`, too.
+    pub fn ident_span_at(&self, pos: BytePos) -> Option<Span> {
+        self.check_pos(pos).ok()?;
+
+        let src = self.src();
+        let local = (pos - self.start_pos()).0 as usize;
+        let lo = src[..local]
+            .char_indices()
+            .rev()
+            .take_while(|&(_, c)| is_ident_char(c))
+            .last()
+            .map_or(local, |(i, _)| i);
+        let hi = src[local..]
+            .char_indices()
+            .find(|&(_, c)| !is_ident_char(c))
+            .map_or(src.len(), |(i, _)| local + i);
+
+        if lo == hi {
+            None
+        } else {
+            Some(self.local_span(lo, hi - lo))
+        }
+    }
+
+    fn words<'a>(&'a self, word: &'a str) -> impl Iterator<Item = Span> + 'a {
+        let src = self.src();
+        src.match_indices(word)
+            .filter(move |&(i, m)| {
+                let before = src[..i].chars().next_back();
+                let after = src[i + m.len()..].chars().next();
+                !before.is_some_and(is_ident_char)
+                    && !after.is_some_and(is_ident_char)
+            })
+            .map(move |(i, m)| self.local_span(i, m.len()))
+    }
+
+    fn local_span(&self, offset: usize, len: usize) -> Span {
+        let lo = self.start_pos() + BytePos(offset as SrcOffset);
+        Span::new(lo, lo + BytePos(len as SrcOffset))
+    }
+}
+
+/// Returns whether the char can be part of an identifier (alphanumeric or
+/// `_`).
+pub fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// --- tests ---
+#[test]
+fn searching() {
+    let mut map = FileMap::new("<dummy>", "let max = x + 1;\nlet x_2 = x * x;\n");
+    map.set_start_pos(BytePos(10));
+    let s = |lo: SrcOffset, hi: SrcOffset| {
+        Span::new(BytePos(10 + lo), BytePos(10 + hi))
+    };
+
+    assert_eq!(map.find_span("x"), Some(s(6, 7)));
+    assert_eq!(map.find_span("y"), None);
+    assert_eq!(map.find_all_spans("let"), [s(0, 3), s(17, 20)]);
+    assert_eq!(map.find_word("x"), Some(s(10, 11)));
+    assert_eq!(map.find_all_words("x"), [s(10, 11), s(27, 28), s(31, 32)]);
+    assert_eq!(map.find_word("ma"), None);
+
+    assert_eq!(map.find_span_on_line(LineIdx(1), "x", 0), Some(s(21, 22)));
+    assert_eq!(map.find_span_on_line(LineIdx(1), "x", 2), Some(s(31, 32)));
+    assert_eq!(map.find_span_on_line(LineIdx(1), "x", 3), None);
+    assert_eq!(map.find_span_on_line(LineIdx(0), ";\n", 0), None);
+    assert_eq!(map.find_span_on_line(LineIdx(5), "x", 0), None);
+
+    assert_eq!(map.ident_span_at(BytePos(10 + 5)), Some(s(4, 7)));
+    assert_eq!(map.ident_span_at(BytePos(10 + 4)), Some(s(4, 7)));
+    assert_eq!(map.ident_span_at(BytePos(10 + 7)), Some(s(4, 7)));
+    assert_eq!(map.ident_span_at(BytePos(10 + 21)), Some(s(21, 24)));
+    assert_eq!(map.ident_span_at(BytePos(10 + 8)), None);
+    assert_eq!(map.ident_span_at(BytePos(3)), None);
+}
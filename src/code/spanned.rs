@@ -0,0 +1,123 @@
+use super::Span;
+use std::ops::{Deref, DerefMut};
+
+/// Types which have a span, like AST nodes. Functions taking an
+/// `H: HasSpan` accept a `Span`, a `Spanned<T>` and references to any
+/// `HasSpan` type.
+pub trait HasSpan {
+    /// Returns the span of `self`.
+    fn span(&self) -> Span;
+}
+
+impl HasSpan for Span {
+    fn span(&self) -> Span {
+        *self
+    }
+}
+
+impl<T: HasSpan + ?Sized> HasSpan for &T {
+    fn span(&self) -> Span {
+        (**self).span()
+    }
+}
+
+/// A value together with the span it was created from. Dereferences to the
+/// value.
+///
+/// The derived `PartialEq` compares both, value and span. Use
+/// `eq_ignore_span` to compare the values only.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
+}
+
+impl<T> Spanned<T> {
+    /// Creates a new spanned value.
+    pub fn new(node: T, span: Span) -> Spanned<T> {
+        Spanned { node, span }
+    }
+
+    /// Creates a new spanned value with a dummy span. Should be used with
+    /// caution.
+    pub fn dummy(node: T) -> Spanned<T> {
+        Spanned::new(node, Span::dummy())
+    }
+
+    /// Returns the value and drops the span.
+    pub fn into_inner(self) -> T {
+        self.node
+    }
+
+    /// Maps the value with the given function and keeps the span.
+    pub fn map<U, F>(self, f: F) -> Spanned<U>
+        where F: FnOnce(T) -> U
+    {
+        Spanned::new(f(self.node), self.span)
+    }
+
+    /// Returns a spanned reference to the value.
+    pub fn as_ref(&self) -> Spanned<&T> {
+        Spanned::new(&self.node, self.span)
+    }
+
+    /// Returns a spanned mutable reference to the value.
+    pub fn as_mut(&mut self) -> Spanned<&mut T> {
+        Spanned::new(&mut self.node, self.span)
+    }
+
+    /// Compares the values only, ignoring the spans.
+    pub fn eq_ignore_span<U>(&self, other: &Spanned<U>) -> bool
+        where T: PartialEq<U>
+    {
+        self.node == other.node
+    }
+}
+
+impl<T> HasSpan for Spanned<T> {
+    fn span(&self) -> Span {
+        self.span
+    }
+}
+
+impl<T> Deref for Spanned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.node
+    }
+}
+
+impl<T> DerefMut for Spanned<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.node
+    }
+}
+
+// --- tests ---
+#[test]
+fn spanned_values() {
+    use super::BytePos;
+
+    let a = Spanned::new(3, Span::new(BytePos(0), BytePos(1)));
+    let b = Spanned::new(3, Span::new(BytePos(5), BytePos(6)));
+
+    assert!(a != b);
+    assert!(a.eq_ignore_span(&b));
+    assert_eq!(*a + 1, 4);
+    assert_eq!(a.map(|x| x * 2), Spanned::new(6, a.span));
+    assert_eq!(a.as_ref().node, &3);
+
+
+    let mut s = Spanned::dummy(String::from("foo"));
+    s.push('!');
+    assert_eq!(s.len(), 4);
+    assert!(s.span().is_dummy());
+
+    let node = Spanned::new(String::from("x"), b.span);
+    let rep = ::diag::Report::simple_error("msg", &node)
+        .with_span_note("note", a);
+    assert_eq!(rep.span, Some(b.span));
+    assert_eq!(rep.remarks[1].snippet.span(), Some(a.span));
+}
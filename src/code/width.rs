@@ -0,0 +1,64 @@
+//! Functions to calculate how wide text is displayed in a terminal or editor
+//!
+
+use unicode_width::UnicodeWidthChar;
+
+/// Returns the number of columns the given char occupies when displayed.
+/// Wide chars (e.g. CJK or most emoji) occupy two columns, combining marks
+/// and control chars none. Tabs are not handled here, since their width
+/// depends on the column they start at (see `advance_visual_col`).
+pub fn char_width(c: char) -> usize {
+    c.width().unwrap_or(0)
+}
+
+/// Returns the visual column after displaying `c` at the visual column
+/// `col`. Tabs advance to the next tab stop (a multiple of `tab_width`).
+pub fn advance_visual_col(col: usize, c: char, tab_width: usize) -> usize {
+    match c {
+        '\t' if tab_width == 0 => col,
+        '\t' => (col / tab_width + 1) * tab_width,
+        _ => col + char_width(c),
+    }
+}
+
+/// Returns the number of columns the string occupies when it is displayed
+/// starting at the visual column `start_col`.
+pub fn visual_width(s: &str, start_col: usize, tab_width: usize) -> usize {
+    s.chars().fold(start_col, |col, c| advance_visual_col(col, c, tab_width))
+        - start_col
+}
+
+/// Replaces all tabs in the string with the correct number of spaces, as if
+/// the string was displayed starting at the visual column `start_col`.
+pub fn expand_tabs(s: &str, start_col: usize, tab_width: usize) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut col = start_col;
+    for c in s.chars() {
+        let next = advance_visual_col(col, c, tab_width);
+        if c == '\t' {
+            out.extend((col..next).map(|_| ' '));
+        } else {
+            out.push(c);
+        }
+        col = next;
+    }
+    out
+}
+
+// --- tests ---
+#[test]
+fn widths() {
+    assert_eq!(visual_width("abc", 0, 4), 3);
+    assert_eq!(visual_width("\t", 0, 4), 4);
+    assert_eq!(visual_width("\t", 2, 4), 2);
+    assert_eq!(visual_width("ab\tc", 0, 4), 5);
+    assert_eq!(visual_width("ab\tc", 0, 8), 9);
+    assert_eq!(visual_width("日本", 0, 4), 4);
+    assert_eq!(visual_width("😀!", 0, 4), 3);
+    // 'e' followed by a combining acute accent
+    assert_eq!(visual_width("e\u{301}", 0, 4), 1);
+
+    assert_eq!(expand_tabs("a\tb", 0, 4), "a   b");
+    assert_eq!(expand_tabs("a\tb", 1, 4), "a  b");
+    assert_eq!(expand_tabs("日\t|", 0, 4), "日  |");
+}
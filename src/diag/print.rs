@@ -1,14 +1,17 @@
 // TODO: get num cols of terminal dynamically
 // TODO: care about the given print options
 
-use super::{Report, ReportKind, RemarkKind, Snippet};
-use code::{FileMap, LineIdx, Span};
+use super::{Report, ReportKind, Remark, RemarkKind, Snippet};
+use code::{FileLookup, FileMap, FileName, LineIdx, Span, ExpnId};
+use code::width::{expand_tabs, visual_width};
 use term_painter::ToStyle;
 use term_painter::Color::*;
 use std::default::Default;
+use std::collections::HashSet;
 
 /// Options for printing on the terminal. By `default()` everything is enabled.
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
 pub struct PrintOptions {
     /// Use of unicode allowed?
     pub unicode: bool,
@@ -16,6 +19,11 @@ pub struct PrintOptions {
     pub color: bool,
     /// Is line wrapping allowed?
     pub line_wrap: bool,
+    /// Number of columns between two tab stops
+    pub tab_width: usize,
+    /// Add a note to snippets which were mapped from generated (and not just
+    /// copied) code back to the original code?
+    pub generated_notes: bool,
 }
 
 impl Default for PrintOptions {
@@ -24,16 +32,37 @@ impl Default for PrintOptions {
             unicode: true,
             color: true,
             line_wrap: true,
+            tab_width: 4,
+            generated_notes: true,
         }
     }
 }
 
-/// Pretty prints a report
+/// Pretty prints a report. All spans are resolved through the given `map`,
+/// which is usually a `CodeMap` or -- if there is only one file -- a
+/// `FileMap`. Every code snippet is printed from the file its span points
+/// into, preceded by the chain of includes which loaded that file (see
+/// `FileMap::with_included_from`). Spans pointing into generated code are
+/// shown at the original code (see `FileMap::add_origin`), if the original
+/// file is known to `map`.
 ///
-/// **Note**: right now, the `PrintOptions` are ignored.
-pub fn print(rep: &Report, src: &FileMap, _: PrintOptions) {
+/// **Note**: right now, only `tab_width` and `generated_notes` of the
+/// `PrintOptions` are used.
+pub fn print<M>(rep: &Report, map: &M, opts: PrintOptions)
+    where M: FileLookup + ?Sized
+{
     trace!("Printing report: {:#?}", rep);
-    trace!("Printing with filemap: {:#?}", src);
+
+    // The main file of the report is the file of the main span or -- if
+    // there is none -- the file of the first remark which has a span. If
+    // no span points into a file, it's the only file (if there is one).
+    let main_file = rep.span
+        .into_iter()
+        .chain(rep.remarks.iter().filter_map(|rem| rem.snippet.span()))
+        .filter_map(|span| reported_filename(map, resolve_origin(map, span).0))
+        .next()
+        .or_else(|| map.single_filemap().map(|f| f.filename()));
+    trace!("Printing with main file: {:?}", main_file);
 
     // print header
     let title = match rep.kind {
@@ -42,26 +71,37 @@ pub fn print(rep: &Report, src: &FileMap, _: PrintOptions) {
     };
 
     let (sep, line) = if let Some(span) = rep.span {
-        (" : ", if span.is_dummy() {
-            "<dummy-span>".into()
-        } else {
-            let start = src.get_loc(span.lo);
-            let end = src.get_loc(span.hi);
-            trace!("Span is from {:?} to {:?}", start, end);
-
-            if start.line != end.line {
-                format!("{}-{}", start.line, end.line)
-            } else {
-                start.line.to_string()
+        let span = resolve_origin(map, span).0;
+        (" : ", match map.span_filemap(span) {
+            _ if span.is_dummy() => "<dummy-span>".into(),
+            None => "<unknown-span>".into(),
+            Some(src) if src.check_span(span).is_err() => {
+                "<invalid-span>".into()
+            }
+            Some(src) => {
+                let start = src.get_loc(span.lo);
+                let end = src.get_loc(span.hi);
+                trace!("Span is from {:?} to {:?}", start, end);
+
+                if start.line != end.line {
+                    format!("{}-{}", start.line, end.line)
+                } else {
+                    start.line.to_string()
+                }
             }
         })
     } else {
         ("", "".into())
     };
 
-    println!("+---- {} in {}{}{} ----+",
+    let filename = match main_file {
+        Some(name) if shows_filename(name) => format!(" in {}", name),
+        _ => "".into(),
+    };
+
+    println!("+---- {}{}{}{} ----+",
         title,
-        src.filename(),
+        filename,
         sep,
         Magenta.bold().paint(line)
     );
@@ -69,97 +109,235 @@ pub fn print(rep: &Report, src: &FileMap, _: PrintOptions) {
 
 
     for rem in &rep.remarks {
-        trace!("Handling Remark {:?}", rem);
+        print_remark(rem, map, main_file, opts);
+
+        // Remarks pointing into expanded code are followed by a note for
+        // every macro invocation which lead to that code. Cyclic expansion
+        // information is only followed until the first repeated expansion.
+        let mut ctxt = rem.snippet.span().map_or(ExpnId::root(), |s| s.ctxt);
+        let mut visited = HashSet::new();
+        for _ in 0..MAX_EXPANSION_NOTES {
+            if !visited.insert(ctxt) {
+                break;
+            }
+            let info = match map.lookup_expansion(ctxt) {
+                Some(info) => info,
+                None => break,
+            };
+
+            let note = Remark::note(
+                format!("in this invocation of macro `{}`", info.macro_name),
+                Snippet::Orig(info.call_site),
+            );
+            print_remark(&note, map, main_file, opts);
+            ctxt = info.call_site.ctxt;
+        }
+    }
+    println!();
+}
 
-        // print message
-        let (title, title_len) = match rem.kind {
-            RemarkKind::Error => (Red.paint("error:"), 6),
-            RemarkKind::Warning => (Yellow.paint("warning:"), 8),
-            RemarkKind::Note => (Green.paint("note:"), 5),
-        };
+/// Maximum depth of "in this macro invocation" notes printed per remark
+const MAX_EXPANSION_NOTES: usize = 32;
+
+fn print_remark<M>(rem: &Remark, map: &M, main_file: Option<&FileName>,
+    opts: PrintOptions)
+    where M: FileLookup + ?Sized
+{
+    trace!("Handling Remark {:?}", rem);
 
-        print!("      =====>  {} ", title);
-        // spaces + big arrow + spaces + title + space
-        let indent = 6 + 6 + 2 + title_len + 1;
-        let block_width = 80 - indent;
-
-        let mut col = 0;
-        for word in rem.desc.split_whitespace() {
-            let word_len = word.chars().count();
-            if col + word_len >= block_width && col != 0 {
-                println!("");
-                print!("           >  {0:>1$} ", " ", title_len);
-                col = 0;
+    // print message
+    let (title, title_len) = match rem.kind {
+        RemarkKind::Error => (Red.paint("error:"), 6),
+        RemarkKind::Warning => (Yellow.paint("warning:"), 8),
+        RemarkKind::Note => (Green.paint("note:"), 5),
+    };
+
+    print!("      =====>  {} ", title);
+    // spaces + big arrow + spaces + title + space
+    let indent = 6 + 6 + 2 + title_len + 1;
+    let block_width = 80 - indent;
+
+    let mut col = 0;
+    for word in rem.desc.split_whitespace() {
+        let word_len = word.chars().count();
+        if col + word_len >= block_width && col != 0 {
+            println!();
+            print!("           >  {0:>1$} ", " ", title_len);
+            col = 0;
+        }
+        print!("{} ", White.bold().paint(word));
+        col += word_len + 1;
+    }
+    println!();
+
+    // print code snippet
+    if let Some(span) = rem.snippet.span() {
+        let (span, generated) = resolve_origin(map, span);
+        match map.span_filemap(span) {
+            _ if span.is_dummy() => {
+                print_snippet_error("no snippet due to <dummy-span>");
+            }
+            None => {
+                print_snippet_error("no snippet: span is not in any file");
             }
-            print!("{} ", White.bold().paint(word));
-            col += word_len + 1;
+            Some(src) => match src.check_span(span) {
+                Err(e) => {
+                    print_snippet_error(&format!("no snippet: {}", e));
+                }
+                Ok(()) => {
+                    let chain = include_chain(map, src);
+                    if !chain.is_empty() {
+                        println!("     in file included from {}",
+                            chain.join(", "),
+                        );
+                    }
+
+                    // Show the filename if it's not obvious from the header
+                    let name = src.reported_filename(src.get_line_idx(span.lo));
+                    if main_file != Some(name) && shows_filename(name) {
+                        println!("     {} {}",
+                            Magenta.bold().paint("-->"),
+                            name,
+                        );
+                    }
+                    print_snippet(src, span, &rem.snippet, opts);
+                }
+            },
         }
-        println!("");
+        println!();
 
-        // print code snippet
-        if let Some(span) = rem.snippet.span() {
-            print_snippet(src, span, &rem.snippet);
-            println!("");
+        if let (Some(src), true) = (generated, opts.generated_notes) {
+            let note = Remark::note(
+                format!("in generated code from `{}`", src.filename()),
+                Snippet::None,
+            );
+            print_remark(&note, map, main_file, opts);
         }
     }
-    println!("");
 }
 
-fn print_snippet(src: &FileMap, span: Span, snippet: &Snippet) {
-    let start = src.get_loc(span.lo);
-    let end = src.get_loc(span.hi);
-    trace!("Span is from {:?} to {:?}", start, end);
+/// Maximum number of files printed in an include chain
+const MAX_INCLUDE_DEPTH: usize = 32;
+
+/// Returns the locations (`file:line`) of the include directives which
+/// (transitively) loaded the given file, innermost first. In case of cyclic
+/// include information, the chain stops before the first file which is
+/// already part of it.
+fn include_chain<M>(map: &M, src: &FileMap) -> Vec<String>
+    where M: FileLookup + ?Sized
+{
+    let mut chain = Vec::new();
+    let mut visited = HashSet::new();
+    visited.insert(src.start_pos());
+    let mut included_from = src.included_from();
+    while let Some(span) = included_from {
+        let file = match map.span_filemap(span) {
+            Some(file) if chain.len() < MAX_INCLUDE_DEPTH => file,
+            _ => break,
+        };
+        if !visited.insert(file.start_pos()) {
+            break;
+        }
+
+        let line = file.get_physical_loc(span.lo).line;
+        chain.push(format!("{}:{}",
+            file.reported_filename(line),
+            file.reported_line(line),
+        ));
+        included_from = file.included_from();
+    }
+    chain
+}
 
+/// Maps a span pointing into generated code back to the original code, if
+/// the original file is known to `map`. Returns the span to show and -- if
+/// the shown code differs from the generated code -- the file containing
+/// the generated code.
+fn resolve_origin<M>(map: &M, span: Span) -> (Span, Option<&FileMap>)
+    where M: FileLookup + ?Sized
+{
+    let generated = match map.span_filemap(span) {
+        Some(src) => src,
+        None => return (span, None),
+    };
 
-    // ----- Dummyspan -----
-    if span.is_dummy() {
-        println!("   {} {} ! no snippet due to <dummy-span>, this is a bug !",
-            Magenta.bold().paint("?"),
-            Magenta.bold().paint("|"),
-        );
+    let origin = generated.origin_of(span);
+    match origin.and_then(|o| o.map_span(span)) {
+        Some(orig) if map.span_filemap(orig).is_some() => {
+            let verbatim = origin.is_some_and(|o| o.verbatim);
+            (orig, if verbatim { None } else { Some(generated) })
+        }
+        _ => (span, None),
     }
+}
+
+/// Returns whether the filename is worth printing. The names of REPL inputs
+/// are not, since the user just typed the code.
+fn shows_filename(name: &FileName) -> bool {
+    !matches!(*name, FileName::Repl(_))
+}
+
+/// Returns the file name to report for the given span, taking line
+/// directives into account.
+fn reported_filename<M>(map: &M, span: Span) -> Option<&FileName>
+    where M: FileLookup + ?Sized
+{
+    map.span_filemap(span)
+        .map(|src| src.reported_filename(src.get_line_idx(span.lo)))
+}
+
+fn print_snippet_error(msg: &str) {
+    println!("   {} {} ! {}, this is a bug !",
+        Magenta.bold().paint("?"),
+        Magenta.bold().paint("|"),
+        msg,
+    );
+}
+
+fn print_snippet(src: &FileMap, span: Span, snippet: &Snippet,
+    opts: PrintOptions)
+{
+    let span_lines: Vec<_> = src.span_lines(span)
+        .expect("span was checked before")
+        .collect();
+    trace!("Span covers lines {:?}", span_lines);
+
+    // We can unwrap, because a span always covers at least one line
+    let first = *span_lines.first().unwrap();
+    let last = *span_lines.last().unwrap();
 
     // ----- Singleline -----
-    else if start.line == end.line {
-        let line_orig = expect_line(src, start.line);
-        trace!("Printing single line span. Orig line: {:?}", line_orig);
-
-        // replace tabs
-        let line = line_orig.replace("\t", "    ");
-        let num_tabs = line_orig[..start.col.0 as usize]
-            .chars()
-            .filter(|&c| c == '\t')
-            .count();
-
-        // adjust cols in case of replaced tabs
-        let startcol = start.col.0 as usize + 3*num_tabs;
-        let endcol = end.col.0 as usize + 3*num_tabs;
-
-        let (middle, underline_len, color) = match *snippet {
-            Snippet::Replace { ref with, .. } => {
-                (&with[..], with.len(), Green)
-            },
-            Snippet::Orig(_) =>  {
-                (&line[startcol..endcol], endcol - startcol, Yellow)
-            },
+    if span_lines.len() == 1 {
+        let line = first;
+        trace!("Printing single line span. Orig line: {:?}", line.text);
+
+        let (middle, color) = match *snippet {
+            Snippet::Replace { ref with, .. } => (&with[..], Green),
+            Snippet::Orig(_) => (line.covered(), Yellow),
             _ => unreachable!(),
         };
 
+        // visual columns of the highlighted part
+        let pre = line.before();
+        let mid_start = visual_width(pre, 0, opts.tab_width);
+        let mid_end = mid_start
+            + visual_width(middle, mid_start, opts.tab_width);
+
         // print the line
         println!("{:>#4} {} {}{}{}",
-            Magenta.bold().paint(start.line),
+            Magenta.bold().paint(src.reported_line(line.line)),
             Magenta.bold().paint("|"),
-            &line[..startcol],
-            color.paint(middle),
-            &line[endcol..],
+            expand_tabs(pre, 0, opts.tab_width),
+            color.paint(expand_tabs(middle, mid_start, opts.tab_width)),
+            expand_tabs(line.after(), mid_end, opts.tab_width),
         );
 
         // print the underline
         color.with(|| {
             println!("      {: <2$}{:^<3$}",
                 " ", "^",
-                startcol + 1,
-                underline_len,
+                mid_start + 1,
+                mid_end - mid_start,
             );
         });
     }
@@ -175,9 +353,7 @@ fn print_snippet(src: &FileMap, span: Span, snippet: &Snippet) {
                     let last_break = with.rfind("\n").unwrap();
 
                     // first line
-                    let line = expect_line(src, start.line);
-                    let startcol = start.col.0 as usize;
-                    lines.push((&line[..startcol], &with[..first_break], ""));
+                    lines.push((first.before(), &with[..first_break], ""));
 
                     // lines in the middle
                     for line in with[..last_break].lines().skip(1) {
@@ -185,42 +361,17 @@ fn print_snippet(src: &FileMap, span: Span, snippet: &Snippet) {
                     }
 
                     // last line
-                    let line = expect_line(src, end.line);
-                    let endcol = end.col.0 as usize;
-                    lines.push(("", &with[last_break + 1..], &line[endcol..]));
+                    lines.push(("", &with[last_break + 1..], last.after()));
 
                     (lines, Green)
                 } else {
-                    let first_line = expect_line(src, start.line);
-                    let startcol = start.col.0 as usize;
-                    let last_line = expect_line(src, end.line);
-                    let endcol = end.col.0 as usize;
-
-                    (vec![(
-                        &first_line[..startcol],
-                        &with[..],
-                        &last_line[endcol..]
-                    )], Green)
+                    (vec![(first.before(), &with[..], last.after())], Green)
                 }
             },
-            Snippet::Orig(_) =>  {
-                let mut lines = Vec::new();
-
-                // first line
-                let line = expect_line(src, start.line);
-                let startcol = start.col.0 as usize;
-                lines.push((&line[..startcol], &line[startcol..], ""));
-
-                // lines in the middle
-                for line_idx in (start.line.0 + 1)..end.line.0 {
-                    let line = expect_line(src, LineIdx(line_idx));
-                    lines.push(("", line, ""));
-                }
-
-                // last line
-                let line = expect_line(src, end.line);
-                let endcol = end.col.0 as usize;
-                lines.push(("", &line[..endcol], &line[endcol..]));
+            Snippet::Orig(_) => {
+                let lines = span_lines.iter()
+                    .map(|l| (l.before(), l.covered(), l.after()))
+                    .collect();
 
                 (lines, Yellow)
             },
@@ -229,17 +380,19 @@ fn print_snippet(src: &FileMap, span: Span, snippet: &Snippet) {
 
 
         for (i, &(pre, middle, post)) in lines.iter().enumerate() {
+            let mid_start = visual_width(pre, 0, opts.tab_width);
+            let mid_end = mid_start
+                + visual_width(middle, mid_start, opts.tab_width);
+
             println!("{:>#4} {} {}{}{}",
-                Magenta.bold().paint(start.line + LineIdx(i as u32)),
+                Magenta.bold().paint(
+                    src.reported_line(first.line + LineIdx(i as u32))
+                ),
                 Magenta.bold().paint("|"),
-                pre,
-                color.paint(middle),
-                post,
+                expand_tabs(pre, 0, opts.tab_width),
+                color.paint(expand_tabs(middle, mid_start, opts.tab_width)),
+                expand_tabs(post, mid_end, opts.tab_width),
             );
         }
     }
 }
-
-fn expect_line(src: &FileMap, line: LineIdx) -> &str {
-    src.get_line(line).expect("`Loc` from FileMap should return a valid line")
-}
@@ -1,12 +1,13 @@
-use code::Span;
+use code::{Span, HasSpan};
 
 /// Describes some kind of problem or occurrence in the code. Contains one or
 /// more remarks with descriptions and separate code spans.
 ///
 /// This type doesn't provide a `Display` impl, since all spans reference an
-/// external filemap which needs to be provided. Use `print` methods of the
-/// `diag` module instead.
+/// external filemap or codemap which needs to be provided. Use `print`
+/// methods of the `diag` module instead.
 #[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
 pub struct Report {
     /// Kind of the report (usually the same as the first remark kind)
     pub kind: ReportKind,
@@ -18,7 +19,11 @@ pub struct Report {
 
 impl Report {
     /// Creates a error report with one message and one span
-    pub fn simple_error<S: Into<String>>(msg: S, span: Span) -> Report {
+    pub fn simple_error<S, H>(msg: S, spanned: H) -> Report
+        where S: Into<String>,
+              H: HasSpan
+    {
+        let span = spanned.span();
         Report {
             kind: ReportKind::Error,
             span: Some(span),
@@ -36,7 +41,11 @@ impl Report {
     }
 
     /// Creates a warning report with one message and one span
-    pub fn simple_warning<S: Into<String>>(msg: S, span: Span) -> Report {
+    pub fn simple_warning<S, H>(msg: S, spanned: H) -> Report
+        where S: Into<String>,
+              H: HasSpan
+    {
+        let span = spanned.span();
         Report {
             kind: ReportKind::Warning,
             span: Some(span),
@@ -50,10 +59,11 @@ impl Report {
     }
 
     /// Adds a note with a span/code snippet to the existing Report
-    pub fn with_span_note<S: Into<String>>(self, msg: S, span: Span)
-        -> Report
+    pub fn with_span_note<S, H>(self, msg: S, spanned: H) -> Report
+        where S: Into<String>,
+              H: HasSpan
     {
-        self.with_remark(Remark::note(msg, Snippet::Orig(span)))
+        self.with_remark(Remark::note(msg, Snippet::orig(spanned)))
     }
 
     /// Adds a remark to the returned Report
@@ -66,6 +76,7 @@ impl Report {
 /// A report can either be an `Error` or a `Warning`. Still pretty similar to
 /// `RemarkType` -- may be merged with it in the future.
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
 pub enum ReportKind {
     /// Something went very wrong and will stop further processing
     Error,
@@ -76,6 +87,7 @@ pub enum ReportKind {
 /// Part of a Report that describes the occurrence with an optional code
 /// snippet.
 #[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
 pub struct Remark {
     pub kind: RemarkKind,
     /// Remark description
@@ -89,9 +101,9 @@ impl Remark {
         -> Self
     {
         Remark {
-            kind: kind,
+            kind,
             desc: desc.into(),
-            snippet: snippet,
+            snippet,
         }
     }
 
@@ -113,6 +125,7 @@ impl Remark {
 
 /// Kinds of remarks
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
 pub enum RemarkKind {
     /// Something went very wrong and will stop further processing
     Error,
@@ -124,6 +137,7 @@ pub enum RemarkKind {
 
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
 pub enum Snippet {
     /// No snippet
     None,
@@ -138,6 +152,11 @@ pub enum Snippet {
 }
 
 impl Snippet {
+    /// Creates a snippet showing the original code of the given span
+    pub fn orig<H: HasSpan>(spanned: H) -> Snippet {
+        Snippet::Orig(spanned.span())
+    }
+
     /// Returns the span if it exists
     pub fn span(&self) -> Option<Span> {
         match *self {
@@ -3,6 +3,11 @@
 #[macro_use]
 extern crate log;
 extern crate term_painter;
+extern crate unicode_width;
+#[cfg(feature = "serde")]
+extern crate serde;
 
 pub mod code;
 pub mod diag;
+#[cfg(feature = "testing")]
+pub mod testing;
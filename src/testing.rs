@@ -0,0 +1,203 @@
+//! Utilities for writing tests against spans and diagnostics. Only available
+//! with the `testing` feature.
+//!
+//! Instead of computing byte offsets by hand, the interesting parts of a
+//! source text are marked directly in the text, like in
+//! `"let [[name: x]] = $0y;"` (see `Fixture`).
+//!
+
+use code::{SrcOffset, BytePos, Span, FileMap, FileName};
+use std::collections::HashMap;
+
+/// A `FileMap` built from source text containing markers, together with the
+/// spans and positions the markers denote. The markers are stripped from
+/// the source. There are two kinds of markers:
+///
+/// - `[[text]]` marks the span of `text`. Spans may be nested and are
+///   numbered in the order of their opening brackets, starting at 0. A span
+///   is named by starting it with an identifier and `": "`, like in
+///   `[[name: text]]`.
+/// - `$0`, `$1`, ... mark positions.
+///
+/// Malformed markers (e.g. unbalanced brackets) cause a panic, since
+/// fixtures are only used in tests. There is no way to escape the markers,
+/// so they can't appear in the source itself.
+#[derive(Debug)]
+pub struct Fixture {
+    file: FileMap,
+    spans: Vec<Span>,
+    names: HashMap<String, usize>,
+    positions: HashMap<u32, BytePos>,
+}
+
+impl Fixture {
+    /// Parses the marked source and builds a file named `<fixture>`.
+    pub fn new(marked: &str) -> Fixture {
+        Fixture::with_filename("<fixture>", marked)
+    }
+
+    /// Parses the marked source and builds a file with the given name.
+    pub fn with_filename<F: Into<FileName>>(filename: F, marked: &str)
+        -> Fixture
+    {
+        let mut src = String::with_capacity(marked.len());
+        let mut spans = Vec::new();
+        let mut names = HashMap::new();
+        let mut positions = HashMap::new();
+        // indices of the spans which are not closed yet
+        let mut open = Vec::new();
+
+        let pos = |src: &String| BytePos(src.len() as SrcOffset);
+        let mut rest = marked;
+        while let Some(c) = rest.chars().next() {
+            if rest.starts_with("[[") {
+                rest = &rest[2..];
+                if let Some(name) = span_name(rest) {
+                    let idx = spans.len();
+                    assert!(names.insert(name.to_owned(), idx).is_none(),
+                        "span `{}` is marked multiple times", name);
+                    rest = &rest[name.len() + 2..];
+                }
+                open.push(spans.len());
+                spans.push(Span::empty_at(pos(&src)));
+            } else if rest.starts_with("]]") {
+                rest = &rest[2..];
+                let idx = open.pop().expect("unexpected `]]` in fixture");
+                spans[idx].hi = pos(&src);
+            } else if let Some(digits) = position_marker(rest) {
+                rest = &rest[1 + digits.len()..];
+                let n = digits.parse().expect("invalid position marker");
+                assert!(positions.insert(n, pos(&src)).is_none(),
+                    "position `${}` is marked multiple times", n);
+            } else {
+                src.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+        assert!(open.is_empty(), "unclosed `[[` in fixture");
+
+        Fixture {
+            file: FileMap::new(filename, src),
+            spans,
+            names,
+            positions,
+        }
+    }
+
+    /// Returns the file built from the source without markers.
+    pub fn file(&self) -> &FileMap {
+        &self.file
+    }
+
+    /// Consumes the fixture and returns the owned file, e.g. to add it to a
+    /// `CodeMap`.
+    pub fn into_file(self) -> FileMap {
+        self.file
+    }
+
+    /// Returns all marked spans in the order of their opening brackets.
+    pub fn spans(&self) -> &[Span] {
+        &self.spans
+    }
+
+    /// Returns the `idx`-th marked span.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are not enough spans.
+    pub fn span(&self, idx: usize) -> Span {
+        *self.spans.get(idx).unwrap_or_else(|| {
+            panic!("fixture has only {} spans", self.spans.len())
+        })
+    }
+
+    /// Returns the span with the given name.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no such span.
+    pub fn named(&self, name: &str) -> Span {
+        match self.names.get(name) {
+            Some(&idx) => self.spans[idx],
+            None => panic!("fixture has no span named `{}`", name),
+        }
+    }
+
+    /// Returns the position marked by `$n`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the position is not marked.
+    pub fn pos(&self, n: u32) -> BytePos {
+        match self.positions.get(&n) {
+            Some(&pos) => pos,
+            None => panic!("fixture has no position `${}`", n),
+        }
+    }
+
+    /// Returns the source code of the given span.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the span is invalid.
+    pub fn snippet(&self, span: Span) -> &str {
+        self.file.span_to_snippet(span).unwrap_or_else(|e| panic!("{}", e))
+    }
+}
+
+/// Returns the name of a named span (`name: ...`) at the start of `s`.
+fn span_name(s: &str) -> Option<&str> {
+    let end = s.find(": ")?;
+    let name = &s[..end];
+    let is_ident = name.chars().next().is_some_and(|c| !c.is_ascii_digit())
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+    if is_ident { Some(name) } else { None }
+}
+
+/// Returns the digits of a position marker (`$12`) at the start of `s`.
+fn position_marker(s: &str) -> Option<&str> {
+    let rest = s.strip_prefix('$')?;
+    let len = rest.bytes().take_while(|b| b.is_ascii_digit()).count();
+    if len == 0 { None } else { Some(&rest[..len]) }
+}
+
+// --- tests ---
+#[test]
+fn markers() {
+    use code::LineIdx;
+
+    let fix = Fixture::with_filename("test.xs", "\
+fn [[name: max]](a, b) {
+    [[if a > b { [[cond: a]] } else { $0b$1 }]]
+}");
+    assert_eq!(fix.file().src(), "\
+fn max(a, b) {
+    if a > b { a } else { b }
+}");
+    assert_eq!(*fix.file().filename(), FileName::from("test.xs"));
+
+    assert_eq!(fix.spans().len(), 3);
+    assert_eq!(fix.snippet(fix.named("name")), "max");
+    assert_eq!(fix.span(0), fix.named("name"));
+    assert_eq!(fix.snippet(fix.span(1)), "if a > b { a } else { b }");
+    assert_eq!(fix.snippet(fix.named("cond")), "a");
+    assert_eq!(fix.snippet(Span::new(fix.pos(0), fix.pos(1))), "b");
+    assert_eq!(fix.file().get_loc(fix.pos(0)).line, LineIdx(1));
+
+    // Not markers
+    let fix = Fixture::new("[[a:b]] $x [[1: y]] ä");
+    assert_eq!(fix.file().src(), "a:b $x 1: y ä");
+    assert_eq!(fix.snippet(fix.span(1)), "1: y");
+}
+
+#[test]
+#[should_panic(expected = "unclosed `[[`")]
+fn unclosed_span() {
+    Fixture::new("[[a [[b]]");
+}
+
+#[test]
+#[should_panic(expected = "fixture has no span named `foo`")]
+fn missing_name() {
+    Fixture::new("[[bar: x]]").named("foo");
+}